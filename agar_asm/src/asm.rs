@@ -1,36 +1,190 @@
 use std::str::FromStr;
 
-use agar_core::{Data, Float, Instruction, OpCode, Operands, Program};
+use agar_core::{
+    Data, DebugInfo, Float, Import, Instruction, Label, OpCode, Operands, Program, SourceLocation,
+};
+
+use crate::lint::allowed;
+use crate::{evaluate, expand, lint, LintConfig, SourceLine, Warning};
+
+/// A name given to a value with `.equ`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constant {
+    pub name: String,
+    pub value: i64,
+}
 
 pub struct Assembler {
     pub source: String,
+    /// Name of the source file recorded in debug info.
+    pub file: String,
+    /// Line being assembled, counted after macro expansion.
     pub line: usize,
+    pub imports: Vec<Import>,
+    pub labels: Vec<Label>,
+    pub constants: Vec<Constant>,
+    /// Lints checked after assembling.
+    pub lints: LintConfig,
+    /// Warnings from the last `parse_source`.
+    pub warnings: Vec<Warning>,
+    /// Message of the error that stopped the last `parse_source`.
+    pub error: Option<String>,
+}
+
+/// Splits a source line into an optional `label:` prefix, the code after it
+/// (without a `;` comment) and the 1-based column where the code starts.
+pub(crate) fn split_line(line: &str) -> (Option<&str>, &str, usize) {
+    let code = match line.find(';') {
+        Some(index) => &line[..index],
+        None => line,
+    };
+    let mut start = code.len() - code.trim_start().len();
+    let mut label = None;
+    if let Some(word) = code[start..].split_whitespace().next() {
+        if let Some(name) = word.strip_suffix(':') {
+            label = Some(name);
+            let rest = &code[start + word.len()..];
+            start += word.len() + rest.len() - rest.trim_start().len();
+        }
+    }
+    let column = code[..start].chars().count() + 1;
+    (label, code[start..].trim_end(), column)
+}
+
+pub(crate) fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_alphabetic() || first == '_' || first == '.' => {
+            chars.all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '.')
+        }
+        _ => false,
+    }
 }
 
 impl Assembler {
     pub fn new(source: String) -> Self {
-        Self { source, line: 0 }
+        Self {
+            source,
+            file: String::new(),
+            line: 0,
+            imports: Vec::new(),
+            labels: Vec::new(),
+            constants: Vec::new(),
+            lints: LintConfig::default(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Value of a constant or the address of a label.
+    pub fn lookup(&self, name: &str) -> Option<i64> {
+        let constant = self.constants.iter().find(|constant| constant.name == name);
+        let label = self.labels.iter().find(|label| label.name == name);
+        constant
+            .map(|constant| constant.value)
+            .or_else(|| label.map(|label| label.ip as i64))
+    }
+
+    /// Resolves a jump or call target given as an expression over addresses,
+    /// labels and constants.
+    pub fn parse_address(&self, expr: &str) -> Result<i64, &'static str> {
+        evaluate(expr, |name| self.lookup(name))
+    }
+
+    /// First pass over the expanded source: records the address of every label.
+    pub fn collect_labels(&mut self, lines: &[SourceLine]) -> Result<(), &'static str> {
+        let mut ip = 0;
+        self.line = 0;
+        for line in lines {
+            let (label, code, _) = split_line(&line.text);
+            if let Some(name) = label {
+                if !is_label_name(name) {
+                    return Err("Invalid label name");
+                }
+                if self.labels.iter().any(|label| label.name == name) {
+                    return Err("Label is defined twice");
+                }
+                self.labels.push(Label {
+                    name: name.to_string(),
+                    ip,
+                });
+            }
+            if !code.is_empty() && !code.starts_with('.') {
+                ip += 1;
+            }
+            self.line += 1;
+        }
+
+        // Constants come after labels so they can use any of them.
+        self.line = 0;
+        for line in lines {
+            let (_, code, _) = split_line(&line.text);
+            if code.split_whitespace().next() == Some(".equ") {
+                self.parse_equ(code)?;
+            }
+            self.line += 1;
+        }
+        Ok(())
+    }
+
+    /// Parses `.equ <name> <expr>` and defines the constant.
+    pub fn parse_equ(&mut self, line: &str) -> Result<(), &'static str> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, expr) = match (words.get(1), words.len()) {
+            (Some(name), 3..) => (*name, words[2..].join(" ")),
+            _ => return Err("Not enough arguments for .equ directive"),
+        };
+        if !is_label_name(name) {
+            return Err("Invalid constant name");
+        }
+        if self.lookup(name).is_some() {
+            return Err("Constant name is already defined");
+        }
+        let value = self.parse_address(&expr)?;
+        self.constants.push(Constant {
+            name: name.to_string(),
+            value,
+        });
+        Ok(())
+    }
+
+    /// Parses `.import <name> <arity>` and adds the host function to the import table.
+    pub fn parse_import(&mut self, line: &str) -> Result<(), &'static str> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() > 3 {
+            return Err("Too many arguments for .import directive");
+        }
+        let (name, arity) = match (words.get(1), words.get(2)) {
+            (Some(name), Some(arity)) => (name, arity),
+            _ => return Err("Not enough arguments for .import directive"),
+        };
+        let arity = if let Ok(n) = arity.parse::<usize>() {
+            n
+        } else {
+            return Err("Can't read import arity");
+        };
+        if self.imports.iter().any(|import| import.name == *name) {
+            return Err("Native function is imported twice");
+        }
+        self.imports.push(Import {
+            name: name.to_string(),
+            arity,
+        });
+        Ok(())
     }
 
     pub fn parse_line(&self, line: &str) -> Result<Instruction, &'static str> {
         let words: Vec<&str> = line.split_whitespace().collect();
         match words.first() {
             Some(&"pushi") => {
-                if words.len() > 2 {
-                    return Err("Too many arguments for PushI instruction");
-                }
-                if let Some(num) = words.get(1) {
-                    if let Ok(n) = num.parse::<i64>() {
-                        return Ok(Instruction {
-                            op_code: OpCode::PushInt,
-                            operands: Operands::One(Data::Int(n)),
-                        });
-                    } else {
-                        return Err("Can't read Int const");
-                    }
-                } else {
+                if words.len() < 2 {
                     return Err("Not enough arguments for PushI instruction");
                 }
+                let n = evaluate(&words[1..].join(" "), |name| self.lookup(name))?;
+                return Ok(Instruction {
+                    op_code: OpCode::PushInt,
+                    operands: Operands::One(Data::Int(n)),
+                });
             }
             Some(&"pushf") => {
                 if words.len() > 2 {
@@ -76,6 +230,62 @@ impl Assembler {
                     operands: Operands::Zero,
                 });
             }
+            Some(&"jump") => {
+                if words.len() < 2 {
+                    return Err("Not enough arguments for Jump instruction");
+                }
+                let target = self.parse_address(&words[1..].join(" "))?;
+                return Ok(Instruction {
+                    op_code: OpCode::Jump,
+                    operands: Operands::One(Data::Int(target)),
+                });
+            }
+            Some(&"cjump") => {
+                if words.len() < 2 {
+                    return Err("Not enough arguments for CJump instruction");
+                }
+                let target = self.parse_address(&words[1..].join(" "))?;
+                return Ok(Instruction {
+                    op_code: OpCode::CJump,
+                    operands: Operands::One(Data::Int(target)),
+                });
+            }
+            Some(&"eq") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Eq instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Eq,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"gr") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Gr instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Gr,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"less") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Less instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Less,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"not") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Not instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Not,
+                    operands: Operands::Zero,
+                });
+            }
             Some(&"dup") => {
                 if words.len() > 1 {
                     return Err("Too many arguments for Dup instruction");
@@ -103,6 +313,97 @@ impl Assembler {
                     operands: Operands::Zero,
                 });
             }
+            Some(&"printerr") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for PrintErr instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::PrintErr,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"readint") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for ReadInt instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::ReadInt,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"readchar") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for ReadChar instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::ReadChar,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"readline") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for ReadLine instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::ReadLine,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"call") => {
+                if words.len() < 2 {
+                    return Err("Not enough arguments for Call instruction");
+                }
+                let target = self.parse_address(&words[1..].join(" "))?;
+                return Ok(Instruction {
+                    op_code: OpCode::Call,
+                    operands: Operands::One(Data::Int(target)),
+                });
+            }
+            Some(&"ret") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Ret instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Ret,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"alloc") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Alloc instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Alloc,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"load") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Load instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Load,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"store") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Store instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Store,
+                    operands: Operands::Zero,
+                });
+            }
+            Some(&"pop") => {
+                if words.len() > 1 {
+                    return Err("Too many arguments for Pop instruction");
+                }
+                return Ok(Instruction {
+                    op_code: OpCode::Pop,
+                    operands: Operands::Zero,
+                });
+            }
             Some(&"panic") => {
                 if words.len() > 1 {
                     return Err("Too many arguments for Panic instruction");
@@ -121,6 +422,23 @@ impl Assembler {
                     operands: Operands::Zero,
                 });
             }
+            Some(&"callnative") => {
+                if words.len() > 2 {
+                    return Err("Too many arguments for CallNative instruction");
+                }
+                if let Some(name) = words.get(1) {
+                    if let Some(id) = self.imports.iter().position(|i| i.name == *name) {
+                        return Ok(Instruction {
+                            op_code: OpCode::CallNative,
+                            operands: Operands::One(Data::Int(id as i64)),
+                        });
+                    } else {
+                        return Err("Native function is not imported");
+                    }
+                } else {
+                    return Err("Not enough arguments for CallNative instruction");
+                }
+            }
             Some(&"nop") => {
                 if words.len() > 1 {
                     return Err("Too many arguments for NOP instruction");
@@ -132,21 +450,69 @@ impl Assembler {
             }
             _ => {}
         }
-        Err("")
+        Err("Unknown instruction")
+    }
+
+    fn report(&mut self, line: &SourceLine, e: &str) {
+        self.error = Some(line.error(e));
     }
 
+    /// Assembles `source`. Can be called again, e.g. after editing `source`;
+    /// everything collected by the previous call is dropped first.
     pub fn parse_source(&mut self) -> Option<Program> {
+        self.imports.clear();
+        self.labels.clear();
+        self.constants.clear();
+        self.warnings.clear();
+        self.error = None;
+        let lines = match expand(&self.source) {
+            Ok(lines) => lines,
+            Err((line, e)) => {
+                self.report(&line, e);
+                return None;
+            }
+        };
+        if let Err(e) = self.collect_labels(&lines) {
+            self.report(&lines[self.line], e);
+            return None;
+        }
+
         let mut ops = Vec::new();
-        for line in self.source.lines() {
-            match self.parse_line(line) {
-                Ok(instr) => ops.push(instr),
-                Err(e) => {
-                    println!("Error: {e}");
-                    return None;
-                }
+        let mut locations = Vec::new();
+        let mut allows = Vec::new();
+        self.line = 0;
+        for line in &lines {
+            let (_, code, column) = split_line(&line.text);
+            let result = if code.is_empty() {
+                Ok(())
+            } else if code.starts_with(".import") {
+                self.parse_import(code)
+            } else if code.starts_with(".equ") {
+                Ok(())
+            } else {
+                self.parse_line(code).map(|instr| {
+                    allows.push(allowed(&line.text));
+                    let (line, column) = line.site(column);
+                    ops.push(instr);
+                    locations.push(SourceLocation { line, column });
+                })
+            };
+            if let Err(e) = result {
+                self.report(line, e);
+                return None;
             }
             self.line += 1;
         }
-        Some(Program { ops })
+        let program = Program {
+            ops,
+            imports: self.imports.clone(),
+            debug: Some(DebugInfo {
+                file: self.file.clone(),
+                locations,
+                labels: self.labels.clone(),
+            }),
+        };
+        self.warnings = lint(&program, &self.source, &allows, &self.lints);
+        Some(program)
     }
 }
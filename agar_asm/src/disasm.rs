@@ -0,0 +1,44 @@
+use std::fmt::Write;
+
+use agar_core::Program;
+
+/// Turns a program back into assembly source that assembles to the same bytecode.
+/// Each instruction is followed by a comment with its address and, when debug
+/// info is present, the line it was assembled from.
+pub fn disassemble(program: &Program) -> String {
+    let mut out = String::new();
+
+    if let Some(debug) = &program.debug {
+        let _ = writeln!(out, "; source: {}", debug.file);
+    }
+    for import in &program.imports {
+        let _ = writeln!(out, ".import {} {}", import.name, import.arity);
+    }
+
+    for (ip, instr) in program.ops.iter().enumerate() {
+        let debug = program.debug.as_ref();
+        for label in debug.iter().flat_map(|debug| &debug.labels) {
+            if label.ip == ip {
+                let _ = writeln!(out, "{}:", label.name);
+            }
+        }
+        let text = program.format_instruction(instr);
+        match debug.and_then(|debug| debug.location(ip)) {
+            Some(location) => {
+                let _ = writeln!(out, "    {text:<24}; {ip} line {}", location.line);
+            }
+            None => {
+                let _ = writeln!(out, "    {text:<24}; {ip}");
+            }
+        }
+    }
+    // Labels past the last instruction, like an `end:` that jumps leave by.
+    let debug = program.debug.as_ref();
+    for label in debug.iter().flat_map(|debug| &debug.labels) {
+        if label.ip >= program.ops.len() {
+            let _ = writeln!(out, "{}:", label.name);
+        }
+    }
+
+    out
+}
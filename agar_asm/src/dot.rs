@@ -0,0 +1,79 @@
+use std::fmt::Write;
+
+use agar_core::cfg;
+use agar_core::{Data, OpCode, Operands, Program};
+
+/// Escapes `text` for a double-quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Renders the control-flow graph of `program` in Graphviz DOT format. Every
+/// basic block lists its labels and instructions, with source lines when debug
+/// info is present, and its immediate dominator. Calls are drawn as dashed
+/// edges to the called block.
+pub fn cfg_dot(program: &Program) -> String {
+    let cfg = cfg::build(program);
+    let debug = program.debug.as_ref();
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph cfg {{");
+    let _ = writeln!(out, "    node [shape=box, fontname=\"monospace\"];");
+
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        let mut label = format!("b{index}");
+        if let Some(dominator) = cfg.immediate_dominator(index) {
+            let _ = write!(label, "  idom b{dominator}");
+        }
+        label.push_str("\\l");
+        for ip in block.start..block.end {
+            for name in debug.iter().flat_map(|debug| &debug.labels) {
+                if name.ip == ip {
+                    let _ = write!(label, "{}:\\l", escape(&name.name));
+                }
+            }
+            let text = escape(&program.format_instruction(&program.ops[ip]));
+            match debug.and_then(|debug| debug.location(ip)) {
+                Some(location) => {
+                    let _ = write!(label, "{ip:>4}: {text:<20} line {}\\l", location.line);
+                }
+                None => {
+                    let _ = write!(label, "{ip:>4}: {text}\\l");
+                }
+            }
+        }
+        let _ = writeln!(out, "    b{index} [label=\"{label}\"];");
+
+        let last = &program.ops[block.end - 1];
+        let taken = match (last.op_code, last.operands) {
+            (OpCode::CJump, Operands::One(Data::Int(target))) => Some(target),
+            _ => None,
+        };
+        for &next in &block.successors {
+            let start = cfg.blocks[next].start as i64;
+            match taken {
+                Some(target) if target == start => {
+                    let _ = writeln!(out, "    b{index} -> b{next} [label=\"taken\"];");
+                }
+                _ => {
+                    let _ = writeln!(out, "    b{index} -> b{next};");
+                }
+            }
+        }
+        for instr in &program.ops[block.start..block.end] {
+            let entry = match (instr.op_code, instr.operands) {
+                (OpCode::Call, Operands::One(Data::Int(entry))) => entry,
+                _ => continue,
+            };
+            let called = usize::try_from(entry).ok().and_then(|ip| cfg.block_at(ip));
+            if let Some(called) = called {
+                let _ = writeln!(
+                    out,
+                    "    b{index} -> b{called} [style=dashed, label=\"call\"];"
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(out, "}}");
+    out
+}
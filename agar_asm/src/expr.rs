@@ -0,0 +1,129 @@
+/// Binary operators from the loosest binding to the tightest.
+const LEVELS: [&[&str]; 5] = [&["|"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Number(i64),
+    Name(&'a str),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(ch) = rest.chars().next() {
+        let len = if ch.is_ascii_digit() {
+            let len = rest
+                .find(|ch: char| !ch.is_alphanumeric())
+                .unwrap_or(rest.len());
+            let n = rest[..len].parse().map_err(|_| "Can't read Int const")?;
+            tokens.push(Token::Number(n));
+            len
+        } else if ch.is_alphabetic() || ch == '_' || ch == '.' {
+            let len = rest
+                .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(&rest[..len]));
+            len
+        } else if ch == '(' || ch == ')' {
+            tokens.push(if ch == '(' { Token::Open } else { Token::Close });
+            1
+        } else {
+            let op = LEVELS
+                .iter()
+                .flat_map(|level| level.iter())
+                .find(|op| rest.starts_with(**op))
+                .ok_or("Unexpected character in expression")?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a, F> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    lookup: F,
+}
+
+impl<'a, F: Fn(&str) -> Option<i64>> Parser<'a, F> {
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, &'static str> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos).copied() {
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            value = apply(op, value, rhs)?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, &'static str> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) => (self.lookup)(name).ok_or("Unknown label or constant"),
+            Some(Token::Op("-")) => self.unary()?.checked_neg().ok_or("Overflow in expression"),
+            Some(Token::Open) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err("Missing closing parenthesis"),
+                }
+            }
+            Some(_) => Err("Unexpected token in expression"),
+            None => Err("Unexpected end of expression"),
+        }
+    }
+}
+
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, &'static str> {
+    let shift = || u32::try_from(b).map_err(|_| "Invalid shift amount");
+    let value = match op {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" | "%" if b == 0 => return Err("Division by zero"),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        "<<" => a.checked_shl(shift()?),
+        ">>" => a.checked_shr(shift()?),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        _ => unreachable!(),
+    };
+    value.ok_or("Overflow in expression")
+}
+
+/// Evaluates an integer expression with `+ - * / % << >> & |`, unary minus and
+/// parentheses. Names are resolved with `lookup`. Operators bind like in C.
+pub fn evaluate<F: Fn(&str) -> Option<i64>>(text: &str, lookup: F) -> Result<i64, &'static str> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Ok(n);
+    }
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        lookup,
+    };
+    let value = parser.binary(0)?;
+    match parser.next() {
+        None => Ok(value),
+        Some(Token::Close) => Err("Unexpected closing parenthesis"),
+        Some(_) => Err("Unexpected token in expression"),
+    }
+}
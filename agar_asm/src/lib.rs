@@ -1,3 +1,18 @@
 mod asm;
+mod disasm;
+mod dot;
+mod expr;
+mod lint;
+mod listing;
+mod macros;
 
 pub use crate::asm::*;
+pub use crate::disasm::*;
+pub use crate::dot::*;
+pub use crate::expr::*;
+pub use crate::lint::*;
+pub use crate::listing::*;
+pub use crate::macros::*;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use agar_core::cfg::Cfg;
+use agar_core::{Data, OpCode, Operands, Program};
+
+use crate::asm::split_line;
+
+/// Suspicious code the assembler warns about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// Instructions that no path from the entry point reaches.
+    Unreachable,
+    /// A label nothing refers to.
+    UnusedLabel,
+    /// A `cjump` whose condition stays on the stack for good on some path.
+    CjumpLeak,
+    /// An instruction at the start of the program popping an empty stack.
+    Underflow,
+    /// `printchar` of a constant that isn't a Unicode scalar value.
+    InvalidChar,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::Unreachable,
+        Lint::UnusedLabel,
+        Lint::CjumpLeak,
+        Lint::Underflow,
+        Lint::InvalidChar,
+    ];
+
+    /// Name used in `-W`/`-A` flags and `allow(...)` comments.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::Unreachable => "unreachable",
+            Lint::UnusedLabel => "unused-label",
+            Lint::CjumpLeak => "cjump-leak",
+            Lint::Underflow => "underflow",
+            Lint::InvalidChar => "invalid-char",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+/// Which lints are reported. All of them are by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintConfig {
+    enabled: Vec<Lint>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            enabled: Lint::ALL.to_vec(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled.contains(&lint)
+    }
+
+    pub fn warn(&mut self, lint: Lint) {
+        if !self.is_enabled(lint) {
+            self.enabled.push(lint);
+        }
+    }
+
+    pub fn allow(&mut self, lint: Lint) {
+        self.enabled.retain(|enabled| *enabled != lint);
+    }
+
+    /// Applies a `-W`/`-A` argument: a lint name or `all`.
+    pub fn set(&mut self, name: &str, warn: bool) -> Result<(), &'static str> {
+        let lints = match name {
+            "all" => Lint::ALL.to_vec(),
+            name => vec![Lint::from_name(name).ok_or("Unknown lint")?],
+        };
+        for lint in lints {
+            if warn {
+                self.warn(lint);
+            } else {
+                self.allow(lint);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub lint: Lint,
+    /// Source line, starting at 1.
+    pub line: usize,
+    /// Instruction the warning is about; `None` for labels.
+    pub ip: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Warning at line {}: {} [{}]",
+            self.line,
+            self.message,
+            self.lint.name()
+        )
+    }
+}
+
+/// Lints suppressed on a line with an `; allow(name, ...)` comment.
+pub(crate) fn allowed(line: &str) -> Vec<Lint> {
+    let comment = match line.find(';') {
+        Some(index) => &line[index + 1..],
+        None => return Vec::new(),
+    };
+    let mut lints = Vec::new();
+    for (start, _) in comment.match_indices("allow(") {
+        let list = &comment[start + "allow(".len()..];
+        let list = &list[..list.find(')').unwrap_or(list.len())];
+        lints.extend(
+            list.split(',')
+                .filter_map(|name| Lint::from_name(name.trim())),
+        );
+    }
+    lints
+}
+
+/// Checks an assembled program for suspicious code. `source` is the text it
+/// was assembled from; the program must carry its debug info. `allows` holds
+/// the lints allowed for each instruction by comments on the lines it was
+/// expanded from, which for macros aren't the line it is reported at.
+pub fn lint(
+    program: &Program,
+    source: &str,
+    allows: &[Vec<Lint>],
+    config: &LintConfig,
+) -> Vec<Warning> {
+    let debug = match &program.debug {
+        Some(debug) => debug,
+        None => return Vec::new(),
+    };
+    let line_of = |ip: usize| debug.location(ip).map_or(0, |location| location.line);
+    let mut warnings = Vec::new();
+    let warning = |lint: Lint, ip: usize, message: String| Warning {
+        lint,
+        line: line_of(ip),
+        ip: Some(ip),
+        message,
+    };
+
+    let cfg = Cfg::build(program);
+    let reachable = cfg.reachable(program);
+    let mut previous = true;
+    for (block, reachable) in cfg.blocks.iter().zip(&reachable) {
+        if !reachable && previous {
+            warnings.push(warning(
+                Lint::Unreachable,
+                block.start,
+                "unreachable instruction".to_string(),
+            ));
+        }
+        previous = *reachable;
+    }
+
+    unused_labels(source, &mut warnings);
+
+    for (ip, instr) in program.ops.iter().enumerate() {
+        if instr.op_code == OpCode::CJump && leaks(program, ip) {
+            warnings.push(warning(
+                Lint::CjumpLeak,
+                ip,
+                "the condition of this cjump is never popped on some path".to_string(),
+            ));
+        }
+    }
+
+    if let Some(ip) = entry_underflow(program) {
+        let mnemonic = program.ops[ip].op_code.mnemonic();
+        warnings.push(warning(
+            Lint::Underflow,
+            ip,
+            format!("`{mnemonic}` runs on an empty stack"),
+        ));
+    }
+
+    for (ip, pair) in program.ops.windows(2).enumerate() {
+        let value = match (pair[0].operands, pair[1].op_code) {
+            (Operands::One(Data::Int(value)), OpCode::PrintChar)
+                if pair[0].op_code == OpCode::PushInt
+                    && cfg.block_at(ip) == cfg.block_at(ip + 1) =>
+            {
+                value
+            }
+            _ => continue,
+        };
+        if u32::try_from(value).ok().and_then(char::from_u32).is_none() {
+            warnings.push(warning(
+                Lint::InvalidChar,
+                ip + 1,
+                format!("{value} is not a valid character"),
+            ));
+        }
+    }
+
+    let lines: Vec<&str> = source.lines().collect();
+    warnings.retain(|warning| {
+        let line = lines.get(warning.line.wrapping_sub(1)).copied();
+        let expanded = warning.ip.and_then(|ip| allows.get(ip));
+        config.is_enabled(warning.lint)
+            && !allowed(line.unwrap_or("")).contains(&warning.lint)
+            && !expanded.is_some_and(|allows| allows.contains(&warning.lint))
+    });
+    warnings
+}
+
+/// Reports labels that no operand in `source` mentions.
+fn unused_labels(source: &str, warnings: &mut Vec<Warning>) {
+    let mut labels = Vec::new();
+    let mut used = HashSet::new();
+    for (index, line) in source.lines().enumerate() {
+        let (label, code, _) = split_line(line);
+        if let Some(name) = label {
+            labels.push((name, index + 1));
+        }
+        let operands = code.split_whitespace().skip(1);
+        let words = operands.flat_map(|word| word.split(|ch: char| !is_name_char(ch)));
+        for word in words {
+            used.insert(word);
+        }
+    }
+    for (name, line) in labels {
+        if !used.contains(name) {
+            warnings.push(Warning {
+                lint: Lint::UnusedLabel,
+                line,
+                ip: None,
+                message: format!("label `{name}` is never used"),
+            });
+        }
+    }
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '.'
+}
+
+/// Values `op_code` pops and pushes, or `None` when that depends on more than
+/// the instruction. Instructions that only read the top, like `dup`, pop nothing.
+fn stack_effect(program: &Program, ip: usize) -> Option<(usize, usize)> {
+    let instr = &program.ops[ip];
+    Some(match instr.op_code {
+        OpCode::Nop | OpCode::Jump | OpCode::CJump | OpCode::Exit | OpCode::Panic => (0, 0),
+        OpCode::PushInt | OpCode::PushFloat | OpCode::Dup | OpCode::Not => (0, 1),
+        OpCode::ReadInt | OpCode::ReadChar => (0, 1),
+        OpCode::Add | OpCode::Sub | OpCode::Mul => (2, 1),
+        OpCode::Eq | OpCode::Gr | OpCode::Less => (2, 1),
+        OpCode::Print | OpCode::PrintErr | OpCode::PrintChar | OpCode::Pop => (1, 0),
+        OpCode::Alloc | OpCode::Load => (1, 1),
+        OpCode::Store => (2, 0),
+        OpCode::CallNative => match instr.operands {
+            Operands::One(Data::Int(id)) => {
+                let import = program.imports.get(usize::try_from(id).ok()?)?;
+                (import.arity, 1)
+            }
+            _ => return None,
+        },
+        OpCode::ReadLine | OpCode::Call | OpCode::Ret => return None,
+    })
+}
+
+/// Values `instr` reads from the stack, whether it pops them or not.
+fn reads(program: &Program, ip: usize) -> Option<usize> {
+    match program.ops[ip].op_code {
+        OpCode::Dup | OpCode::Not | OpCode::CJump => Some(1),
+        _ => stack_effect(program, ip).map(|(pops, _)| pops),
+    }
+}
+
+/// What happens to a `cjump` condition along the paths from an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fate {
+    /// Popped, or the program ends first, on every path.
+    Popped,
+    /// Still on the stack when a path loops.
+    Leaked,
+    /// Some path goes through an instruction with an unknown stack effect.
+    Unknown,
+}
+
+/// Paths longer than this are given up on.
+const MAX_PATH: usize = 4096;
+
+/// Whether the condition left by the `cjump` at `ip` can come back around a
+/// loop, or reach another loop, without anything popping it.
+fn leaks(program: &Program, ip: usize) -> bool {
+    let mut fates = HashMap::new();
+    let mut active = HashMap::new();
+    let successors = agar_core::cfg::successors(program, ip);
+    successors
+        .into_iter()
+        .any(|next| follow(program, next, 0, 0, &mut fates, &mut active) == Fate::Leaked)
+}
+
+/// Follows control from `ip` with the condition `depth` values below the top.
+/// `active` holds the smallest depth the current path passed each instruction
+/// at. Getting back to one of them without the condition getting closer to
+/// the top leaks it; a loop that does bring it closer is followed again.
+/// Fates are kept per depth, since the same instruction may pop the condition
+/// at one depth and not at another.
+fn follow(
+    program: &Program,
+    ip: usize,
+    depth: usize,
+    length: usize,
+    fates: &mut HashMap<(usize, usize), Fate>,
+    active: &mut HashMap<usize, usize>,
+) -> Fate {
+    if let Some(fate) = fates.get(&(ip, depth)) {
+        return *fate;
+    }
+    if active.get(&ip).is_some_and(|passed| *passed <= depth) {
+        return Fate::Leaked;
+    }
+    let fate = match (program.ops[ip].op_code, stack_effect(program, ip)) {
+        (OpCode::Exit | OpCode::Panic, _) => Fate::Popped,
+        _ if length > MAX_PATH => Fate::Unknown,
+        (_, None) => Fate::Unknown,
+        (_, Some((pops, _))) if pops > depth => Fate::Popped,
+        (_, Some((pops, pushes))) => {
+            let outer = active.insert(ip, depth);
+            let after = depth - pops + pushes;
+            let mut fate = Fate::Popped;
+            for next in agar_core::cfg::successors(program, ip) {
+                match follow(program, next, after, length + 1, fates, active) {
+                    Fate::Leaked => fate = Fate::Leaked,
+                    Fate::Unknown if fate == Fate::Popped => fate = Fate::Unknown,
+                    _ => {}
+                }
+            }
+            match outer {
+                Some(outer) => active.insert(ip, outer),
+                None => active.remove(&ip),
+            };
+            fate
+        }
+    };
+    fates.insert((ip, depth), fate);
+    fate
+}
+
+/// First instruction on the straight path from the entry point that needs
+/// more values than the stack holds.
+fn entry_underflow(program: &Program) -> Option<usize> {
+    let mut depth = 0;
+    let mut ip = 0;
+    let mut visited = vec![false; program.ops.len()];
+    while ip < program.ops.len() && !visited[ip] {
+        visited[ip] = true;
+        if reads(program, ip)? > depth {
+            return Some(ip);
+        }
+        let (pops, pushes) = stack_effect(program, ip)?;
+        depth = depth - pops + pushes;
+        ip = match (program.ops[ip].op_code, program.ops[ip].operands) {
+            (OpCode::Jump, Operands::One(Data::Int(target))) => usize::try_from(target).ok()?,
+            (OpCode::CJump | OpCode::Exit | OpCode::Panic, _) => return None,
+            _ => ip + 1,
+        };
+    }
+    None
+}
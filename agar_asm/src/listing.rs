@@ -0,0 +1,38 @@
+use std::fmt::Write;
+
+use agar_core::Program;
+
+/// Renders `source` next to the code assembled from each line, with every
+/// operand as the number it was folded to. Lines that expand to several
+/// instructions, like macro uses, get one row per instruction.
+pub fn listing(program: &Program, source: &str) -> String {
+    let mut rows: Vec<Vec<usize>> = vec![Vec::new(); source.lines().count()];
+    if let Some(debug) = &program.debug {
+        for ip in 0..program.ops.len() {
+            let line = debug.location(ip).map_or(0, |location| location.line);
+            if let Some(row) = line.checked_sub(1).and_then(|index| rows.get_mut(index)) {
+                row.push(ip);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for ((index, text), ips) in source.lines().enumerate().zip(rows) {
+        let line = index + 1;
+        match ips.split_first() {
+            Some((first, rest)) => {
+                let instr = program.ops[*first].to_string();
+                let _ = writeln!(out, "{first:>5}  {instr:<20}{line:>5}  {text}");
+                for ip in rest {
+                    let _ = writeln!(out, "{ip:>5}  {}", program.ops[*ip]);
+                }
+            }
+            None => {
+                let _ = writeln!(out, "{:27}{line:>5}  {text}", "");
+            }
+        }
+    }
+    out.lines()
+        .map(|row| row.trim_end().to_string() + "\n")
+        .collect()
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use agar_core::OpCode;
+
+use crate::asm::{is_label_name, split_line};
+
+/// How deep macros can expand inside each other, which also stops recursion.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// Use of a macro that produced a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A line of source after macro expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub text: String,
+    /// Line the text comes from, starting at 1. For a line from a macro body
+    /// that's the line in the definition.
+    pub line: usize,
+    /// Macros the line was expanded from, innermost first.
+    pub expansions: Vec<Expansion>,
+}
+
+impl SourceLine {
+    /// Line and column in the file that produced this line: the outermost
+    /// macro use, or the line itself.
+    pub fn site(&self, column: usize) -> (usize, usize) {
+        match self.expansions.last() {
+            Some(expansion) => (expansion.line, expansion.column),
+            None => (self.line, column),
+        }
+    }
+
+    /// Error message pointing at this line and the macro uses it came from.
+    /// A recursive chain is cut at the first use that repeats, followed by
+    /// the number of uses left out and the outermost one.
+    pub fn error(&self, e: &str) -> String {
+        let mut shown = Vec::new();
+        for expansion in &self.expansions {
+            if shown.contains(&expansion) {
+                break;
+            }
+            shown.push(expansion);
+        }
+        let mut message = format!("Error at line {}", self.line);
+        let in_macro = |expansion: &Expansion| {
+            format!(
+                " in macro `{}`, expanded at line {}",
+                expansion.name, expansion.line
+            )
+        };
+        for expansion in &shown {
+            message.push_str(&in_macro(expansion));
+        }
+        if shown.len() < self.expansions.len() {
+            let outermost = &self.expansions[self.expansions.len() - 1];
+            let skipped = self.expansions.len() - shown.len() - 1;
+            if skipped > 0 {
+                message.push_str(&format!(" ({skipped} more)"));
+            }
+            message.push_str(&in_macro(outermost));
+        }
+        format!("{message}: {e}")
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    /// Lines of the body with their line numbers.
+    body: Vec<(usize, String)>,
+    /// Labels defined in the body, renamed in every expansion.
+    locals: Vec<String>,
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '.'
+}
+
+/// Replaces every name in `code` that `names` maps to something.
+fn substitute(code: &str, names: &HashMap<&str, String>) -> String {
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    while !rest.is_empty() {
+        let end = rest.find(|ch: char| !is_name_char(ch)).unwrap_or(rest.len());
+        let (word, tail) = if end == 0 {
+            let ch = rest.chars().next().unwrap();
+            rest.split_at(ch.len_utf8())
+        } else {
+            rest.split_at(end)
+        };
+        match names.get(word) {
+            Some(replacement) if end > 0 => out.push_str(replacement),
+            _ => out.push_str(word),
+        }
+        rest = tail;
+    }
+    out
+}
+
+fn is_mnemonic(name: &str) -> bool {
+    (0..=u8::MAX)
+        .filter_map(OpCode::from_byte)
+        .any(|op_code| op_code.mnemonic() == name)
+}
+
+/// Comma separated list after the first word of `code`: macro parameters or
+/// arguments. Arguments can be expressions with spaces, like `SIZE * 2`.
+fn list(code: &str) -> Vec<&str> {
+    let rest = code.trim_start();
+    let rest = rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..].trim();
+    if rest.is_empty() {
+        return Vec::new();
+    }
+    rest.split(',').map(str::trim).collect()
+}
+
+/// The `;` comment of a source line, or nothing.
+fn comment(text: &str) -> &str {
+    &text[text.find(';').unwrap_or(text.len())..]
+}
+
+/// Error at a line of the source file, outside any macro.
+fn at(line: usize, e: &'static str) -> (SourceLine, &'static str) {
+    let line = SourceLine {
+        text: String::new(),
+        line,
+        expansions: Vec::new(),
+    };
+    (line, e)
+}
+
+/// Takes `.macro name param, ... .endm` definitions out of `source` and
+/// expands every use of them. Labels defined in a macro body get a name unique
+/// to each expansion. Expanded lines keep their comments and the comment of
+/// the use they came from, so `allow(...)` comments work inside macros.
+pub fn expand(source: &str) -> Result<Vec<SourceLine>, (SourceLine, &'static str)> {
+    let mut macros = HashMap::new();
+    let mut lines = Vec::new();
+    let mut definition: Option<(String, Macro)> = None;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (label, code, _) = split_line(text);
+        let words: Vec<&str> = code.split_whitespace().collect();
+        match (words.first().copied(), &mut definition) {
+            (Some(".macro"), Some(_)) => return Err(at(line, "Nested macro definition")),
+            (Some(".macro"), None) => {
+                let name = match words.get(1) {
+                    Some(name) if is_label_name(name) => name.to_string(),
+                    Some(_) => return Err(at(line, "Invalid macro name")),
+                    None => return Err(at(line, "Not enough arguments for .macro directive")),
+                };
+                if label.is_some() {
+                    return Err(at(line, "Macro definition can't have a label"));
+                }
+                if is_mnemonic(&name) {
+                    return Err(at(line, "Macro name is an instruction"));
+                }
+                if macros.contains_key(&name) {
+                    return Err(at(line, "Macro is defined twice"));
+                }
+                let params: Vec<String> = list(code[".macro".len()..].trim_start())
+                    .into_iter()
+                    .map(|p| p.to_string())
+                    .collect();
+                if params.iter().any(|param| !is_label_name(param)) {
+                    return Err(at(line, "Invalid macro parameter name"));
+                }
+                if (1..params.len()).any(|i| params[i..].contains(&params[i - 1])) {
+                    return Err(at(line, "Macro parameter is declared twice"));
+                }
+                let body = Macro {
+                    params,
+                    body: Vec::new(),
+                    locals: Vec::new(),
+                };
+                definition = Some((name, body));
+            }
+            (Some(".endm"), None) => return Err(at(line, ".endm without .macro")),
+            (Some(".endm"), Some(_)) => {
+                let (name, body) = definition.take().unwrap();
+                macros.insert(name, body);
+            }
+            (_, Some((_, body))) => {
+                if let Some(name) = label {
+                    body.locals.push(name.to_string());
+                }
+                body.body.push((line, text.to_string()));
+            }
+            (_, None) => lines.push(SourceLine {
+                text: text.to_string(),
+                line,
+                expansions: Vec::new(),
+            }),
+        }
+    }
+    if definition.is_some() {
+        return Err(at(source.lines().count(), "Missing .endm"));
+    }
+
+    let mut expander = Expander {
+        macros,
+        count: 0,
+        out: Vec::new(),
+    };
+    for line in lines {
+        expander.line(line)?;
+    }
+    Ok(expander.out)
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// Expansions so far, numbering local labels.
+    count: usize,
+    out: Vec<SourceLine>,
+}
+
+impl Expander {
+    fn line(&mut self, line: SourceLine) -> Result<(), (SourceLine, &'static str)> {
+        let (label, code, column) = split_line(&line.text);
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let name = match words.first() {
+            Some(name) if self.macros.contains_key(*name) => name.to_string(),
+            _ => {
+                self.out.push(line);
+                return Ok(());
+            }
+        };
+        if line.expansions.len() >= MAX_MACRO_DEPTH {
+            return Err((line, "Macro expansion is too deep"));
+        }
+        let definition = &self.macros[&name];
+        let args = list(code);
+        if args.len() != definition.params.len() {
+            return Err((line, "Wrong number of macro arguments"));
+        }
+        if args.contains(&"") {
+            return Err((line, "Empty macro argument"));
+        }
+
+        self.count += 1;
+        let mut names: HashMap<&str, String> = HashMap::new();
+        for (param, arg) in definition.params.iter().zip(args) {
+            names.insert(param, arg.to_string());
+        }
+        for local in &definition.locals {
+            names.insert(local, format!("{name}.{local}.{}", self.count));
+        }
+
+        let mut expansions = vec![Expansion {
+            name: name.clone(),
+            line: line.line,
+            column,
+        }];
+        expansions.extend(line.expansions.iter().cloned());
+        if let Some(label) = label {
+            self.out.push(SourceLine {
+                text: format!("{label}:"),
+                line: line.line,
+                expansions: line.expansions.clone(),
+            });
+        }
+        let body: Vec<SourceLine> = definition
+            .body
+            .iter()
+            .map(|(body_line, text)| {
+                let code = &text[..text.len() - comment(text).len()];
+                let comment = format!("{} {}", comment(text), comment(&line.text));
+                SourceLine {
+                    text: format!("{}{}", substitute(code, &names), comment.trim_end()),
+                    line: *body_line,
+                    expansions: expansions.clone(),
+                }
+            })
+            .collect();
+        for line in body {
+            self.line(line)?;
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,83 @@
 use std::{env, fs, path::Path};
 
-use agar_asm::Assembler;
+use agar_asm::{cfg_dot, disassemble, listing, Assembler, LintConfig};
+use agar_core::Program;
+
+const USAGE: &str = "Usage: agar_asm [--strip] [-O] [--cfg <out.dot>] [--listing <out.lst>] [-W <lint>] [-A <lint>] <source.aa>\n       agar_asm --disasm <source.ab>";
 
 fn main() -> Result<(), ()> {
-    let args: Vec<String> = env::args().collect();
-    if let Some(raw_path) = args.get(1) {
-        let path = Path::new(raw_path);
-        let source: String = std::fs::read_to_string(path).expect("Can't read source file");
-        let mut asm = Assembler::new(source);
-        if let Some(program) = asm.parse_source() {
-            let bytecode = program.to_bytes();
-            fs::write(path.with_extension("ab"), bytecode).expect("Can't save bytecode in file");
-        } else {
-            println!("Can't parse source file")
+    let mut strip = false;
+    let mut optimize = false;
+    let mut disasm = false;
+    let mut cfg_out = None;
+    let mut listing_out = None;
+    let mut lints = LintConfig::default();
+    let mut source = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strip" => strip = true,
+            "-O" => optimize = true,
+            "--cfg" => cfg_out = args.next(),
+            "--listing" => listing_out = args.next(),
+            "-W" | "-A" => {
+                let name = args.next().unwrap_or_default();
+                if let Err(e) = lints.set(&name, arg == "-W") {
+                    println!("{e}: {name}");
+                    return Err(());
+                }
+            }
+            "-d" | "--disasm" => disasm = true,
+            _ => source = Some(arg),
+        }
+    }
+
+    let raw_path = match source {
+        Some(raw_path) => raw_path,
+        None => {
+            println!("{USAGE}");
+            return Err(());
+        }
+    };
+    let path = Path::new(&raw_path);
+
+    if disasm {
+        let bytecode = fs::read(path).expect("Can't read bytecode");
+        match Program::from_bytes(&bytecode) {
+            Ok(program) => print!("{}", disassemble(&program)),
+            Err(e) => println!("Can't parse bytecode: {e}"),
+        }
+        return Ok(());
+    }
+
+    let source: String = std::fs::read_to_string(path).expect("Can't read source file");
+    let mut asm = Assembler::new(source.clone());
+    asm.file = raw_path.clone();
+    asm.lints = lints;
+    let program = asm.parse_source();
+    for warning in &asm.warnings {
+        println!("{warning}");
+    }
+    if let Some(mut program) = program {
+        if optimize {
+            agar_opt::optimize(&mut program);
+        }
+        if let Some(listing_out) = &listing_out {
+            fs::write(listing_out, listing(&program, &source)).expect("Can't save listing");
+        }
+        if let Some(cfg_out) = &cfg_out {
+            fs::write(cfg_out, cfg_dot(&program)).expect("Can't save control-flow graph");
+        }
+        if strip {
+            program.strip();
+        }
+        let bytecode = program.to_bytes();
+        fs::write(path.with_extension("ab"), bytecode).expect("Can't save bytecode in file");
+    } else {
+        if let Some(error) = &asm.error {
+            println!("{error}");
         }
+        println!("Can't parse source file")
     }
 
     Ok(())
@@ -0,0 +1,397 @@
+use agar_core::*;
+
+use crate::*;
+
+fn assemble(source: &str) -> Program {
+    let mut asm = Assembler::new(source.to_string());
+    asm.file = "test.aa".to_string();
+    asm.parse_source().expect("Can't parse source")
+}
+
+#[test]
+fn labels_and_comments() {
+    let program = assemble(
+        "; counts down from 3
+        pushi 3
+loop:   dup         ; keep the counter
+        print
+        pushi 1
+        sub
+        dup
+        cjump loop
+        exit
+",
+    );
+    assert_eq!(program.ops.len(), 8);
+    assert_eq!(program.ops[6].operands, Operands::One(Data::Int(1)));
+
+    let debug = program.debug.unwrap();
+    assert_eq!(debug.file, "test.aa");
+    assert_eq!(debug.label_address("loop"), Some(1));
+    assert_eq!(debug.location(0), Some(SourceLocation { line: 2, column: 9 }));
+    assert_eq!(debug.location(1), Some(SourceLocation { line: 3, column: 9 }));
+    assert_eq!(debug.ip_for_line(8), Some(6));
+    assert_eq!(debug.enclosing_label(4), Some(("loop", 3)));
+}
+
+#[test]
+fn forward_label() {
+    let program = assemble("jump end\npushi 1\nend: exit\n");
+    assert_eq!(program.ops[0].operands, Operands::One(Data::Int(2)));
+}
+
+#[test]
+fn unknown_label() {
+    let mut asm = Assembler::new("jump nowhere".to_string());
+    assert!(asm.parse_source().is_none());
+}
+
+#[test]
+fn debug_info_roundtrip() {
+    let mut program = assemble("start: pushi 1\ncall start\n");
+    let bytecode = program.to_bytes();
+    assert_eq!(Program::from_bytes(&bytecode), Ok(program.clone()));
+
+    program.strip();
+    let stripped = Program::from_bytes(&program.to_bytes()).unwrap();
+    assert_eq!(stripped.debug, None);
+    assert!(program.to_bytes().len() < bytecode.len());
+}
+
+#[test]
+fn disassemble_roundtrip() {
+    let program = assemble(
+        ".import log 1
+main:   pushi 10
+        call body
+        exit
+body:   dup
+        callnative log
+        pushf 1.5
+        ret
+",
+    );
+    let text = disassemble(&program);
+    assert!(text.contains("main:\n    pushi 10"));
+    assert!(text.contains("call body"));
+    assert!(text.contains("callnative log"));
+    assert!(text.contains("; 3 line 5"));
+
+    let reassembled = assemble(&text);
+    assert_eq!(reassembled.ops, program.ops);
+    assert_eq!(reassembled.imports, program.imports);
+
+    let program = assemble("        pushi 0\n        cjump end\nend:\n");
+    let text = disassemble(&program);
+    assert!(text.ends_with("\nend:\n"));
+    assert_eq!(assemble(&text).ops, program.ops);
+}
+
+#[test]
+fn assemble_twice() {
+    let mut asm = Assembler::new(
+        ".import log 1
+.equ SIZE 2
+start:  pushi SIZE
+        callnative log
+        jump start
+"
+        .to_string(),
+    );
+    let program = asm.parse_source().unwrap();
+    assert_eq!(asm.parse_source(), Some(program));
+    assert_eq!(asm.imports.len(), 1);
+
+    asm.source = "        pushi 1\n        frobnicate\n".to_string();
+    assert_eq!(asm.parse_source(), None);
+    asm.source = "        pushi 1\n".to_string();
+    assert!(asm.parse_source().is_some());
+    assert_eq!(asm.error, None);
+}
+
+#[test]
+fn cfg_as_dot() {
+    let program = assemble(
+        "        pushi 3
+loop:   dup
+        call show
+        pushi 1
+        sub
+        dup
+        cjump loop
+        exit
+show:   print
+        ret
+",
+    );
+    let dot = cfg_dot(&program);
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.contains("b1 [label=\"b1  idom b0\\lloop:\\l   1: dup"));
+    assert!(dot.contains("   6: cjump loop           line 7\\l"));
+    assert!(dot.contains("    b1 -> b2;\n    b1 -> b1 [label=\"taken\"];\n"));
+    assert!(dot.contains("    b1 -> b3 [style=dashed, label=\"call\"];\n"));
+    assert!(dot.ends_with("}\n"));
+}
+
+fn warnings(source: &str, lints: LintConfig) -> Vec<(Lint, usize)> {
+    let mut asm = Assembler::new(source.to_string());
+    asm.lints = lints;
+    asm.parse_source().expect("Can't parse source");
+    asm.warnings.iter().map(|w| (w.lint, w.line)).collect()
+}
+
+#[test]
+fn lints() {
+    let source = "        pop
+unused: pushi 1114112
+        printchar
+        pushi 5
+loop:   readint
+        print
+        pushi 1
+        cjump loop
+        exit
+        pushi 2
+        print
+quiet:  exit            ; allow(unused-label)
+";
+    assert_eq!(
+        warnings(source, LintConfig::default()),
+        vec![
+            (Lint::Unreachable, 10),
+            (Lint::UnusedLabel, 2),
+            (Lint::CjumpLeak, 8),
+            (Lint::Underflow, 1),
+            (Lint::InvalidChar, 3),
+        ]
+    );
+
+    let mut lints = LintConfig::default();
+    lints.set("all", false).unwrap();
+    lints.set("unused-label", true).unwrap();
+    assert_eq!(warnings(source, lints), vec![(Lint::UnusedLabel, 2)]);
+    assert!(LintConfig::default().set("nope", true).is_err());
+}
+
+#[test]
+fn cjump_leak_depends_on_depth() {
+    let leaks = |source: &str| {
+        let warnings = warnings(source, LintConfig::default());
+        warnings.contains(&(Lint::CjumpLeak, 2))
+    };
+    // `s` pops the condition when reached directly, but only the 5 when
+    // reached through `a`, which then loops with the condition on the stack.
+    let source = "        pushi 1
+        cjump a
+s:      pop
+l:      jump l
+a:      pushi 5
+        jump s
+";
+    assert!(leaks(source));
+    // The first time round, `l` pops the 2; the second time, the condition.
+    let source = "        pushi 0
+        cjump l
+        pushi 2
+l:      pop
+        jump l
+";
+    assert!(!leaks(source));
+}
+
+#[test]
+fn clean_code_has_no_warnings() {
+    let source = "        pushi 3
+loop:   dup
+        call show
+        pushi 1
+        sub
+        dup
+        cjump loop
+        pop
+        pushi 10
+        printchar
+        exit
+show:   print
+        ret
+";
+    assert_eq!(warnings(source, LintConfig::default()), vec![]);
+}
+
+fn error(source: &str) -> String {
+    let mut asm = Assembler::new(source.to_string());
+    assert!(asm.parse_source().is_none());
+    asm.error.unwrap()
+}
+
+#[test]
+fn macros() {
+    let source = "        .macro countdown n
+        pushi n
+loop:   dup
+        print
+        pushi 1
+        sub
+        dup
+        cjump loop
+        pop
+        .endm
+        .macro twice n
+        countdown n
+        countdown n
+        .endm
+start:  twice 2
+        exit
+";
+    let program = assemble(source);
+    assert_eq!(program.ops.len(), 17);
+    assert_eq!(program.ops[0].operands, Operands::One(Data::Int(2)));
+    assert_eq!(program.ops[6].operands, Operands::One(Data::Int(1)));
+    assert_eq!(program.ops[14].operands, Operands::One(Data::Int(9)));
+
+    let debug = program.debug.unwrap();
+    assert_eq!(debug.label_address("start"), Some(0));
+    assert_eq!(debug.label_address("countdown.loop.2"), Some(1));
+    assert_eq!(debug.label_address("countdown.loop.3"), Some(9));
+    assert_eq!(debug.location(3), Some(SourceLocation { line: 15, column: 9 }));
+    assert_eq!(debug.location(16), Some(SourceLocation { line: 16, column: 9 }));
+
+    let source = ".equ SIZE 4
+        .macro pair a, b
+        pushi a
+        pushi b
+        .endm
+        pair SIZE * 2, (SIZE - 1) << 1
+";
+    let program = assemble(source);
+    assert_eq!(program.ops[0].operands, Operands::One(Data::Int(8)));
+    assert_eq!(program.ops[1].operands, Operands::One(Data::Int(6)));
+}
+
+#[test]
+fn allow_inside_macros() {
+    let source = |body: &str, uses: &str| {
+        format!(
+            "        .macro halt
+        exit
+        pushi 1         {body}
+        .endm
+        .macro stop
+        halt            {uses}
+        .endm
+        stop
+"
+        )
+    };
+    let allow = "; allow(unreachable)";
+    let lints = LintConfig::default();
+    assert_eq!(
+        warnings(&source("", ""), lints.clone()),
+        vec![(Lint::Unreachable, 8)]
+    );
+    // On the line in the macro body, or on a use of the macro inside another.
+    assert_eq!(warnings(&source(allow, ""), lints.clone()), vec![]);
+    assert_eq!(warnings(&source("", allow), lints), vec![]);
+}
+
+#[test]
+fn macro_errors() {
+    let source = "        .macro bad
+        pushi 1
+        frobnicate
+        .endm
+        .macro outer
+        bad
+        .endm
+        outer
+";
+    assert_eq!(
+        error(source),
+        "Error at line 3 in macro `bad`, expanded at line 6 in macro `outer`, \
+         expanded at line 8: Unknown instruction"
+    );
+    assert_eq!(
+        error(".macro loop\nloop\n.endm\nloop\n"),
+        "Error at line 2 in macro `loop`, expanded at line 2 (62 more) in macro `loop`, \
+         expanded at line 4: Macro expansion is too deep"
+    );
+    assert_eq!(
+        error(".macro m a\npushi a\n.endm\nm\n"),
+        "Error at line 4: Wrong number of macro arguments"
+    );
+    assert_eq!(
+        error(".macro m a, b\npushi a\n.endm\nm 1,\n"),
+        "Error at line 4: Empty macro argument"
+    );
+    assert_eq!(error(".macro m\npushi 1\n"), "Error at line 2: Missing .endm");
+    assert_eq!(error(".endm\n"), "Error at line 1: .endm without .macro");
+    assert_eq!(
+        error(".macro dup\n.endm\n"),
+        "Error at line 1: Macro name is an instruction"
+    );
+}
+
+#[test]
+fn constants_and_expressions() {
+    let source = "        .equ SIZE 4
+        .equ MASK (1 << SIZE) - 1
+        .equ NEXT loop + 1
+        pushi SIZE*2
+loop:   pushi -SIZE + 10 % 4 | 8
+        pushi MASK & 12
+        jump loop+1
+        call NEXT * (2 - 1)
+        pushi (SIZE + 1) * 2 >> 1
+";
+    let program = assemble(source);
+    let values: Vec<Operands> = program.ops.iter().map(|instr| instr.operands).collect();
+    assert_eq!(
+        values,
+        vec![
+            Operands::One(Data::Int(8)),
+            Operands::One(Data::Int(-2 | 8)),
+            Operands::One(Data::Int(12)),
+            Operands::One(Data::Int(2)),
+            Operands::One(Data::Int(2)),
+            Operands::One(Data::Int(5)),
+        ]
+    );
+
+    assert_eq!(error("pushi 1 / (2 - 2)"), "Error at line 1: Division by zero");
+    assert_eq!(error("pushi 0x10"), "Error at line 1: Can't read Int const");
+    assert_eq!(error("pushi (1 + 2"), "Error at line 1: Missing closing parenthesis");
+    assert_eq!(error("jump nowhere"), "Error at line 1: Unknown label or constant");
+    assert_eq!(
+        error(".equ A 1\n.equ A 2\n"),
+        "Error at line 2: Constant name is already defined"
+    );
+    assert_eq!(
+        error(".equ B A\n.equ A 1\n"),
+        "Error at line 1: Unknown label or constant"
+    );
+}
+
+#[test]
+fn listing_shows_folded_operands() {
+    let source = "        .equ SIZE 3
+        .macro twice n
+        pushi n
+        pushi n
+        .endm
+loop:   twice SIZE*2
+        jump loop+1
+";
+    let program = assemble(source);
+    assert_eq!(
+        listing(&program, source),
+        "                               1          .equ SIZE 3
+                               2          .macro twice n
+                               3          pushi n
+                               4          pushi n
+                               5          .endm
+    0  pushi 6                 6  loop:   twice SIZE*2
+    1  pushi 6
+    2  jump 1                  7          jump loop+1
+"
+    );
+}
@@ -0,0 +1,263 @@
+//! Control-flow graph of a program, split into basic blocks.
+
+use crate::{Data, Instruction, OpCode, Operands, Program};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// First instruction of the block.
+    pub start: usize,
+    /// One past the last instruction of the block.
+    pub end: usize,
+    /// Indices of the blocks control can continue to. Calls are not edges:
+    /// a `call` continues with the instruction after it.
+    pub successors: Vec<usize>,
+    /// Indices of the blocks that continue to this one.
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// Block of every instruction.
+    block_of: Vec<usize>,
+    /// Immediate dominator of every block.
+    idom: Vec<Option<usize>>,
+}
+
+/// Builds the control-flow graph of `program`.
+pub fn build(program: &Program) -> Cfg {
+    Cfg::build(program)
+}
+
+/// In-range jump or call target of `instr`, if it has one.
+fn address(instr: &Instruction, len: usize) -> Option<usize> {
+    match instr.operands {
+        Operands::One(Data::Int(ip)) if ip >= 0 && (ip as usize) < len => Some(ip as usize),
+        _ => None,
+    }
+}
+
+/// Instructions that control can reach right after the one at `ip`, without
+/// entering calls. Falling off the end of the program isn't a successor.
+pub fn successors(program: &Program, ip: usize) -> Vec<usize> {
+    let len = program.ops.len();
+    let instr = match program.get(ip) {
+        Some(instr) => instr,
+        None => return Vec::new(),
+    };
+    let mut next = match instr.op_code {
+        OpCode::Exit | OpCode::Panic | OpCode::Ret => Vec::new(),
+        OpCode::Jump => address(instr, len).into_iter().collect(),
+        OpCode::CJump => {
+            let mut next = vec![ip + 1];
+            next.extend(address(instr, len));
+            next
+        }
+        _ => vec![ip + 1],
+    };
+    next.retain(|ip| *ip < len);
+    next.dedup();
+    next
+}
+
+/// Whether control can leave the instruction other than by falling through.
+fn ends_block(op_code: OpCode) -> bool {
+    matches!(
+        op_code,
+        OpCode::Exit | OpCode::Panic | OpCode::Ret | OpCode::Jump | OpCode::CJump
+    )
+}
+
+impl Cfg {
+    pub fn build(program: &Program) -> Self {
+        let len = program.ops.len();
+        if len == 0 {
+            return Self::default();
+        }
+
+        let mut leaders = vec![false; len];
+        leaders[0] = true;
+        for (ip, instr) in program.ops.iter().enumerate() {
+            if matches!(instr.op_code, OpCode::Jump | OpCode::CJump | OpCode::Call) {
+                if let Some(target) = address(instr, len) {
+                    leaders[target] = true;
+                }
+            }
+            if ends_block(instr.op_code) && ip + 1 < len {
+                leaders[ip + 1] = true;
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut block_of = vec![0; len];
+        for ip in 0..len {
+            if leaders[ip] {
+                blocks.push(BasicBlock {
+                    start: ip,
+                    end: ip,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+            }
+            let block = blocks.len() - 1;
+            blocks[block].end = ip + 1;
+            block_of[ip] = block;
+        }
+        for block in blocks.iter_mut() {
+            let mut successors: Vec<usize> = successors(program, block.end - 1)
+                .into_iter()
+                .map(|ip| block_of[ip])
+                .collect();
+            successors.dedup();
+            block.successors = successors;
+        }
+        for index in 0..blocks.len() {
+            for next in blocks[index].successors.clone() {
+                blocks[next].predecessors.push(index);
+            }
+        }
+
+        // Functions are entered through calls rather than edges, so every
+        // call target is a root of its own dominator tree.
+        let mut roots = vec![0];
+        for instr in &program.ops {
+            if instr.op_code == OpCode::Call {
+                roots.extend(address(instr, len).map(|ip| block_of[ip]));
+            }
+        }
+        let idom = dominators(&blocks, &roots);
+
+        Self {
+            blocks,
+            block_of,
+            idom,
+        }
+    }
+
+    /// Index of the block containing `ip`.
+    pub fn block_at(&self, ip: usize) -> Option<usize> {
+        self.block_of.get(ip).copied()
+    }
+
+    /// Marks the blocks that can run, starting from the entry point and
+    /// following edges and the calls made by blocks that can run.
+    pub fn reachable(&self, program: &Program) -> Vec<bool> {
+        let len = program.ops.len();
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            if index >= reachable.len() || reachable[index] {
+                continue;
+            }
+            reachable[index] = true;
+            let block = &self.blocks[index];
+            pending.extend(&block.successors);
+            for instr in &program.ops[block.start..block.end] {
+                if instr.op_code == OpCode::Call {
+                    pending.extend(address(instr, len).map(|ip| self.block_of[ip]));
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Closest block other than `block` that every path from the entry point
+    /// (or from the entry of the function) to `block` goes through. `None` for
+    /// entry points and blocks that can't be reached.
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        self.idom.get(block).copied().flatten()
+    }
+
+    /// Whether every path to `block` goes through `dominator`. A block
+    /// dominates itself.
+    pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if block == dominator {
+                return true;
+            }
+            current = self.immediate_dominator(block);
+        }
+        false
+    }
+}
+
+/// Immediate dominators, computed with the iterative algorithm of Cooper,
+/// Harvey and Kennedy. A virtual block above all `roots` keeps it to one tree.
+fn dominators(blocks: &[BasicBlock], roots: &[usize]) -> Vec<Option<usize>> {
+    let top = blocks.len();
+    let predecessors = |block: usize| {
+        let mut predecessors = blocks[block].predecessors.clone();
+        if roots.contains(&block) {
+            predecessors.push(top);
+        }
+        predecessors
+    };
+
+    // Reverse postorder from the virtual block.
+    let mut order = Vec::new();
+    let mut visited = vec![false; top + 1];
+    let mut stack = vec![(top, 0)];
+    visited[top] = true;
+    while let Some((block, next)) = stack.pop() {
+        let successors = if block == top {
+            roots
+        } else {
+            &blocks[block].successors[..]
+        };
+        match successors.get(next) {
+            Some(&successor) => {
+                stack.push((block, next + 1));
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, 0));
+                }
+            }
+            None => order.push(block),
+        }
+    }
+    order.reverse();
+    let mut position = vec![usize::MAX; top + 1];
+    for (i, block) in order.iter().enumerate() {
+        position[*block] = i;
+    }
+
+    let mut idom = vec![None; top + 1];
+    idom[top] = Some(top);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order[1..] {
+            let mut new: Option<usize> = None;
+            for predecessor in predecessors(block) {
+                if idom[predecessor].is_none() {
+                    continue;
+                }
+                new = Some(match new {
+                    None => predecessor,
+                    Some(mut a) => {
+                        let mut b = predecessor;
+                        while a != b {
+                            while position[a] > position[b] {
+                                a = idom[a].unwrap();
+                            }
+                            while position[b] > position[a] {
+                                b = idom[b].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new.is_some() && idom[block] != new {
+                idom[block] = new;
+                changed = true;
+            }
+        }
+    }
+
+    idom.truncate(top);
+    idom.into_iter()
+        .map(|dominator| dominator.filter(|dominator| *dominator != top))
+        .collect()
+}
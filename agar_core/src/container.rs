@@ -0,0 +1,116 @@
+//! Versioned container shared by bytecode and VM snapshots: 4-byte magic,
+//! little-endian `u16` version and a list of `(id: u8, len: u32, payload)`
+//! sections. Readers skip sections they don't know.
+
+use crate::{Data, Float, Int};
+
+const DATA_INT: u8 = 0;
+const DATA_FLOAT: u8 = 1;
+
+/// Starts a container with the given magic and version.
+pub fn write_header(bytes: &mut Vec<u8>, magic: &[u8; 4], version: u16) {
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+}
+
+pub fn write_section(bytes: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    bytes.push(id);
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+}
+
+/// Checks the header and splits the container into `(id, payload)` sections.
+pub fn read_sections<'a>(
+    bytes: &'a [u8],
+    magic: &[u8; 4],
+    version: u16,
+) -> Result<Vec<(u8, &'a [u8])>, &'static str> {
+    if bytes.get(..4) != Some(magic.as_slice()) {
+        return Err("Container doesn't start with the expected magic");
+    }
+    let found = match bytes.get(4..6) {
+        Some(slice) => u16::from_le_bytes([slice[0], slice[1]]),
+        None => return Err("Can't read container version"),
+    };
+    if found != version {
+        return Err("Unsupported container version");
+    }
+
+    let mut sections = Vec::new();
+    let mut i = 6;
+    while i < bytes.len() {
+        let id = bytes[i];
+        let len = match read_u32(bytes, i + 1) {
+            Some(len) => len as usize,
+            None => return Err("Can't read section length"),
+        };
+        let payload = match bytes.get(i + 5..i + 5 + len) {
+            Some(payload) => payload,
+            None => return Err("Section is longer than container"),
+        };
+        sections.push((id, payload));
+        i += 5 + len;
+    }
+    Ok(sections)
+}
+
+pub fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Reads a length-prefixed string, returning it with the offset right after it.
+pub fn read_str(bytes: &[u8], at: usize) -> Option<(&str, usize)> {
+    let len = read_u32(bytes, at)? as usize;
+    let slice = bytes.get(at + 4..at + 4 + len)?;
+    Some((std::str::from_utf8(slice).ok()?, at + 4 + len))
+}
+
+pub fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let slice = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes(slice.try_into().ok()?))
+}
+
+pub fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    let slice = bytes.get(at..at + 8)?;
+    Some(u64::from_le_bytes(slice.try_into().ok()?))
+}
+
+/// Writes a tagged value: `0` and 8 bytes for `Int`, `1` and 16 bytes for `Float`.
+pub fn write_data(bytes: &mut Vec<u8>, data: &Data) {
+    match data {
+        Data::Int(a) => {
+            bytes.push(DATA_INT);
+            bytes.extend_from_slice(&a.to_le_bytes());
+        }
+        Data::Float(a) => {
+            bytes.push(DATA_FLOAT);
+            bytes.extend_from_slice(&a.serialize());
+        }
+    }
+}
+
+/// Reads a value written by `write_data`, returning it with the offset right after it.
+pub fn read_data(bytes: &[u8], at: usize) -> Option<(Data, usize)> {
+    match *bytes.get(at)? {
+        DATA_INT => {
+            let slice = bytes.get(at + 1..at + 9)?;
+            Some((Data::Int(Int::from_le_bytes(slice.try_into().ok()?)), at + 9))
+        }
+        DATA_FLOAT => {
+            let slice = bytes.get(at + 1..at + 17)?;
+            Some((Data::Float(Float::deserialize(slice.try_into().ok()?)), at + 17))
+        }
+        _ => None,
+    }
+}
+
+/// 64-bit FNV-1a hash, used to tie snapshots to the program they were taken from.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -12,6 +12,15 @@ pub enum Data {
     Float(Float),
 }
 
+impl Data {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Data::Int(_) => "Int",
+            Data::Float(_) => "Float",
+        }
+    }
+}
+
 impl Add<Data> for Data {
     type Output = Option<Data>;
     fn add(self, rhs: Data) -> Self::Output {
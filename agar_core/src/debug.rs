@@ -0,0 +1,56 @@
+/// Position in the assembly source. Lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub ip: usize,
+}
+
+/// Optional bytecode section linking instructions back to the source they came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub file: String,
+    /// Source location of every instruction, indexed by `ip`.
+    pub locations: Vec<SourceLocation>,
+    pub labels: Vec<Label>,
+}
+
+impl DebugInfo {
+    pub fn location(&self, ip: usize) -> Option<SourceLocation> {
+        self.locations.get(ip).copied()
+    }
+
+    /// First instruction generated from `line`.
+    pub fn ip_for_line(&self, line: usize) -> Option<usize> {
+        self.locations.iter().position(|loc| loc.line == line)
+    }
+
+    /// Name of a label pointing exactly at `ip`.
+    pub fn label_at(&self, ip: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|label| label.ip == ip)
+            .map(|label| label.name.as_str())
+    }
+
+    pub fn label_address(&self, name: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|label| label.name == name)
+            .map(|label| label.ip)
+    }
+
+    /// Closest label at or before `ip`, with the distance from it.
+    pub fn enclosing_label(&self, ip: usize) -> Option<(&str, usize)> {
+        self.labels
+            .iter()
+            .filter(|label| label.ip <= ip)
+            .max_by_key(|label| label.ip)
+            .map(|label| (label.name.as_str(), ip - label.ip))
+    }
+}
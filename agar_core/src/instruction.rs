@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use crate::data::Data;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -19,6 +21,22 @@ pub enum OpCode {
     Not,
     Jump,
     CJump,
+    CallNative,
+    /// Reads a line holding an integer. Unlike the other reads it fails at the
+    /// end of input, since -1 is a value it can read.
+    ReadInt,
+    ReadChar,
+    /// Pushes the characters of the line in reverse order followed by their count,
+    /// so that popping yields the line from its start.
+    ReadLine,
+    PrintErr,
+    Call,
+    Ret,
+    Alloc,
+    Load,
+    Store,
+    /// Discards the top of the stack.
+    Pop,
 }
 
 impl OpCode {
@@ -26,6 +44,40 @@ impl OpCode {
         *self as u8
     }
 
+    /// Name of the instruction in assembly source.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::Nop => "nop",
+            OpCode::PushInt => "pushi",
+            OpCode::PushFloat => "pushf",
+            OpCode::Add => "add",
+            OpCode::Sub => "sub",
+            OpCode::Mul => "mul",
+            OpCode::Dup => "dup",
+            OpCode::Print => "print",
+            OpCode::Exit => "exit",
+            OpCode::Panic => "panic",
+            OpCode::PrintChar => "printchar",
+            OpCode::Eq => "eq",
+            OpCode::Gr => "gr",
+            OpCode::Less => "less",
+            OpCode::Not => "not",
+            OpCode::Jump => "jump",
+            OpCode::CJump => "cjump",
+            OpCode::CallNative => "callnative",
+            OpCode::ReadInt => "readint",
+            OpCode::ReadChar => "readchar",
+            OpCode::ReadLine => "readline",
+            OpCode::PrintErr => "printerr",
+            OpCode::Call => "call",
+            OpCode::Ret => "ret",
+            OpCode::Alloc => "alloc",
+            OpCode::Load => "load",
+            OpCode::Store => "store",
+            OpCode::Pop => "pop",
+        }
+    }
+
     pub fn from_byte(num: u8) -> Option<Self> {
         Some(match num {
             _x if num == OpCode::Nop.as_byte() => OpCode::Nop,
@@ -45,6 +97,17 @@ impl OpCode {
             _x if num == OpCode::Not.as_byte() => OpCode::Not,
             _x if num == OpCode::Jump.as_byte() => OpCode::Jump,
             _x if num == OpCode::CJump.as_byte() => OpCode::CJump,
+            _x if num == OpCode::CallNative.as_byte() => OpCode::CallNative,
+            _x if num == OpCode::ReadInt.as_byte() => OpCode::ReadInt,
+            _x if num == OpCode::ReadChar.as_byte() => OpCode::ReadChar,
+            _x if num == OpCode::ReadLine.as_byte() => OpCode::ReadLine,
+            _x if num == OpCode::PrintErr.as_byte() => OpCode::PrintErr,
+            _x if num == OpCode::Call.as_byte() => OpCode::Call,
+            _x if num == OpCode::Ret.as_byte() => OpCode::Ret,
+            _x if num == OpCode::Alloc.as_byte() => OpCode::Alloc,
+            _x if num == OpCode::Load.as_byte() => OpCode::Load,
+            _x if num == OpCode::Store.as_byte() => OpCode::Store,
+            _x if num == OpCode::Pop.as_byte() => OpCode::Pop,
             _ => return None,
         })
     }
@@ -61,3 +124,12 @@ pub enum Operands {
     Zero,
     One(Data),
 }
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.operands {
+            Operands::Zero => write!(f, "{}", self.op_code.mnemonic()),
+            Operands::One(data) => write!(f, "{} {}", self.op_code.mnemonic(), data),
+        }
+    }
+}
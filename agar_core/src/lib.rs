@@ -1,9 +1,15 @@
+pub mod cfg;
+pub mod container;
 mod data;
+mod debug;
 mod instruction;
 mod program;
+mod verify;
 #[cfg(test)]
 mod tests;
 
 pub use data::*;
+pub use debug::*;
 pub use program::*;
 pub use instruction::*;
+pub use verify::*;
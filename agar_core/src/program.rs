@@ -1,88 +1,254 @@
-use crate::{Data, Float, Instruction, Int, OpCode, Operands};
+use crate::container::{
+    fnv1a, read_sections, read_str, read_u32, write_header, write_section, write_str,
+};
+use crate::{Data, DebugInfo, Float, Instruction, Int, Label, OpCode, Operands, SourceLocation};
+
+const MAGIC: &[u8; 4] = b"AGAR";
+const VERSION: u16 = 1;
+
+const SECTION_CODE: u8 = 1;
+const SECTION_IMPORTS: u8 = 2;
+const SECTION_DEBUG: u8 = 3;
+
+/// Host function that the program expects the VM to provide under `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub name: String,
+    pub arity: usize,
+}
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Program {
     pub ops: Vec<Instruction>,
+    pub imports: Vec<Import>,
+    pub debug: Option<DebugInfo>,
 }
 
 impl Program {
     pub fn new() -> Self {
-        Self { ops: Vec::new() }
+        Self {
+            ops: Vec::new(),
+            imports: Vec::new(),
+            debug: None,
+        }
+    }
+
+    /// Drops debug info, e.g. for release bytecode.
+    pub fn strip(&mut self) {
+        self.debug = None;
     }
 
     pub fn get(&self, index: usize) -> Option<&Instruction> {
         self.ops.get(index)
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
-        let mut ops = Vec::new();
-
-        let mut i = 0;
-        while i < bytes.len() {
-            if let Some(opcode) = OpCode::from_byte(bytes[i]) {
-                match opcode {
-                    OpCode::PushInt => {
-                        if let Ok(slice) = bytes[i + 1..i + 9].try_into() {
-                            let int = Int::from_le_bytes(slice);
-                            ops.push(Instruction {
-                                op_code: opcode,
-                                operands: Operands::One(Data::Int(int)),
-                            });
-                            i += 9;
-                        } else {
-                            return Err("Can't read Int constant from bytecode");
-                        }
-                    }
-                    OpCode::PushFloat => {
-                        if let Ok(slice) = bytes[i + 1..i + 17].try_into() {
-                            let float = Float::deserialize(slice);
-                            ops.push(Instruction {
-                                op_code: opcode,
-                                operands: Operands::One(Data::Float(float)),
-                            });
-                            i += 17;
-                        } else {
-                            return Err("Can't read Int constant from bytecode");
-                        }
-                    }
-                    _ => {
-                        ops.push(Instruction {
-                            op_code: opcode,
-                            operands: Operands::Zero,
-                        });
-                        i += 1;
-                    }
+    pub fn import_index(&self, name: &str) -> Option<usize> {
+        self.imports.iter().position(|import| import.name == name)
+    }
+
+    /// Renders an instruction, naming jump targets and imports where possible.
+    pub fn format_instruction(&self, instr: &Instruction) -> String {
+        match (instr.op_code, instr.operands) {
+            (OpCode::Jump | OpCode::CJump | OpCode::Call, Operands::One(Data::Int(target))) => {
+                let label = self
+                    .debug
+                    .as_ref()
+                    .and_then(|debug| debug.label_at(target as usize));
+                match label {
+                    Some(name) => format!("{} {}", instr.op_code.mnemonic(), name),
+                    None => instr.to_string(),
+                }
+            }
+            (OpCode::CallNative, Operands::One(Data::Int(id))) => {
+                match self.imports.get(id as usize) {
+                    Some(import) => format!("{} {}", instr.op_code.mnemonic(), import.name),
+                    None => instr.to_string(),
                 }
             }
+            _ => instr.to_string(),
         }
+    }
 
-        Ok(Self { ops })
+    /// Reads a bytecode container: `AGAR` magic, little-endian `u16` version and a
+    /// list of `(id: u8, len: u32, payload)` sections. Unknown sections are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut program = Self::new();
+        for (id, payload) in read_sections(bytes, MAGIC, VERSION)? {
+            match id {
+                SECTION_CODE => program.ops = decode_ops(payload)?,
+                SECTION_IMPORTS => program.imports = decode_imports(payload)?,
+                SECTION_DEBUG => program.debug = Some(decode_debug(payload)?),
+                _ => {}
+            }
+        }
+
+        Ok(program)
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
+        write_header(&mut bytes, MAGIC, VERSION);
 
-        for instr in &self.ops {
-            bytes.push(instr.op_code.as_byte());
-            match instr.op_code {
-                OpCode::PushInt => {
-                    if let Operands::One(Data::Int(num)) = instr.operands {
-                        for byte in num.to_le_bytes() {
-                            bytes.push(byte);
-                        }
-                    }
-                }
-                OpCode::PushFloat => {
-                    if let Operands::One(Data::Float(num)) = instr.operands {
-                        for byte in num.serialize() {
-                            bytes.push(byte);
-                        }
-                    }
-                }
-                _ => {}
-            }
+        write_section(&mut bytes, SECTION_CODE, &encode_ops(&self.ops));
+        if !self.imports.is_empty() {
+            write_section(&mut bytes, SECTION_IMPORTS, &encode_imports(&self.imports));
+        }
+        if let Some(debug) = &self.debug {
+            write_section(&mut bytes, SECTION_DEBUG, &encode_debug(debug));
         }
 
         bytes
     }
+
+    /// Hash of the code and imports. Debug info doesn't affect it, so stripped
+    /// and unstripped builds of the same source hash the same.
+    pub fn hash(&self) -> u64 {
+        let mut bytes = encode_ops(&self.ops);
+        bytes.extend_from_slice(&encode_imports(&self.imports));
+        fnv1a(&bytes)
+    }
+}
+
+fn has_int_operand(op_code: OpCode) -> bool {
+    matches!(
+        op_code,
+        OpCode::PushInt | OpCode::Jump | OpCode::CJump | OpCode::CallNative | OpCode::Call
+    )
+}
+
+fn decode_ops(bytes: &[u8]) -> Result<Vec<Instruction>, &'static str> {
+    let mut ops = Vec::new();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let opcode = match OpCode::from_byte(bytes[i]) {
+            Some(opcode) => opcode,
+            None => return Err("Unknown opcode in bytecode"),
+        };
+        if has_int_operand(opcode) {
+            if let Some(Ok(slice)) = bytes.get(i + 1..i + 9).map(|s| s.try_into()) {
+                let int = Int::from_le_bytes(slice);
+                ops.push(Instruction {
+                    op_code: opcode,
+                    operands: Operands::One(Data::Int(int)),
+                });
+                i += 9;
+            } else {
+                return Err("Can't read Int constant from bytecode");
+            }
+        } else if opcode == OpCode::PushFloat {
+            if let Some(Ok(slice)) = bytes.get(i + 1..i + 17).map(|s| s.try_into()) {
+                let float = Float::deserialize(slice);
+                ops.push(Instruction {
+                    op_code: opcode,
+                    operands: Operands::One(Data::Float(float)),
+                });
+                i += 17;
+            } else {
+                return Err("Can't read Float constant from bytecode");
+            }
+        } else {
+            ops.push(Instruction {
+                op_code: opcode,
+                operands: Operands::Zero,
+            });
+            i += 1;
+        }
+    }
+
+    Ok(ops)
+}
+
+fn encode_ops(ops: &[Instruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for instr in ops {
+        bytes.push(instr.op_code.as_byte());
+        match instr.operands {
+            Operands::One(Data::Int(num)) if has_int_operand(instr.op_code) => {
+                bytes.extend_from_slice(&num.to_le_bytes());
+            }
+            Operands::One(Data::Float(num)) if instr.op_code == OpCode::PushFloat => {
+                bytes.extend_from_slice(&num.serialize());
+            }
+            _ => {}
+        }
+    }
+
+    bytes
+}
+
+fn decode_imports(bytes: &[u8]) -> Result<Vec<Import>, &'static str> {
+    let count = read_u32(bytes, 0).ok_or("Can't read import count")?;
+    let mut imports = Vec::new();
+
+    let mut i = 4;
+    for _ in 0..count {
+        let arity = read_u32(bytes, i).ok_or("Can't read import arity")? as usize;
+        let (name, next) = read_str(bytes, i + 4).ok_or("Can't read import name")?;
+        imports.push(Import {
+            name: name.to_string(),
+            arity,
+        });
+        i = next;
+    }
+
+    Ok(imports)
+}
+
+fn encode_imports(imports: &[Import]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(imports.len() as u32).to_le_bytes());
+    for import in imports {
+        bytes.extend_from_slice(&(import.arity as u32).to_le_bytes());
+        write_str(&mut bytes, &import.name);
+    }
+    bytes
+}
+
+fn decode_debug(bytes: &[u8]) -> Result<DebugInfo, &'static str> {
+    let (file, mut i) = read_str(bytes, 0).ok_or("Can't read debug file name")?;
+    let mut debug = DebugInfo {
+        file: file.to_string(),
+        ..Default::default()
+    };
+
+    let count = read_u32(bytes, i).ok_or("Can't read line table")?;
+    i += 4;
+    for _ in 0..count {
+        let line = read_u32(bytes, i).ok_or("Can't read line table")? as usize;
+        let column = read_u32(bytes, i + 4).ok_or("Can't read line table")? as usize;
+        debug.locations.push(SourceLocation { line, column });
+        i += 8;
+    }
+
+    let count = read_u32(bytes, i).ok_or("Can't read label table")?;
+    i += 4;
+    for _ in 0..count {
+        let ip = read_u32(bytes, i).ok_or("Can't read label table")? as usize;
+        let (name, next) = read_str(bytes, i + 4).ok_or("Can't read label name")?;
+        debug.labels.push(Label {
+            name: name.to_string(),
+            ip,
+        });
+        i = next;
+    }
+
+    Ok(debug)
+}
+
+fn encode_debug(debug: &DebugInfo) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_str(&mut bytes, &debug.file);
+    bytes.extend_from_slice(&(debug.locations.len() as u32).to_le_bytes());
+    for location in &debug.locations {
+        bytes.extend_from_slice(&(location.line as u32).to_le_bytes());
+        bytes.extend_from_slice(&(location.column as u32).to_le_bytes());
+    }
+    bytes.extend_from_slice(&(debug.labels.len() as u32).to_le_bytes());
+    for label in &debug.labels {
+        bytes.extend_from_slice(&(label.ip as u32).to_le_bytes());
+        write_str(&mut bytes, &label.name);
+    }
+    bytes
 }
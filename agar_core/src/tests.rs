@@ -6,8 +6,273 @@ fn program_eq() {
         op_code: OpCode::PushInt,
         operands: Operands::One(Data::Int(15)),
     }];
-    let program = Program { ops };
+    let program = Program {
+        ops,
+        ..Default::default()
+    };
     let bytecode = program.to_bytes();
     let deser_program = Program::from_bytes(&bytecode);
     assert_eq!(Ok(program), deser_program);
 }
+
+#[test]
+fn program_imports_eq() {
+    let ops = vec![
+        Instruction {
+            op_code: OpCode::CallNative,
+            operands: Operands::One(Data::Int(1)),
+        },
+        Instruction {
+            op_code: OpCode::Jump,
+            operands: Operands::One(Data::Int(0)),
+        },
+    ];
+    let imports = vec![
+        Import {
+            name: "log".to_string(),
+            arity: 1,
+        },
+        Import {
+            name: "db_get".to_string(),
+            arity: 2,
+        },
+    ];
+    let program = Program {
+        ops,
+        imports,
+        ..Default::default()
+    };
+    let bytecode = program.to_bytes();
+    let deser_program = Program::from_bytes(&bytecode);
+    assert_eq!(Ok(program), deser_program);
+}
+
+#[test]
+fn program_bad_bytecode() {
+    assert!(Program::from_bytes(b"").is_err());
+    assert!(Program::from_bytes(&[1, 2, 3]).is_err());
+
+    let mut bytecode = Program::new().to_bytes();
+    bytecode.extend_from_slice(&[1, 1, 0, 0, 0, 255]);
+    assert!(Program::from_bytes(&bytecode).is_err());
+}
+
+#[test]
+fn program_hash() {
+    let mut program = Program {
+        ops: vec![Instruction {
+            op_code: OpCode::PushInt,
+            operands: Operands::One(Data::Int(15)),
+        }],
+        debug: Some(DebugInfo::default()),
+        ..Default::default()
+    };
+    let hash = program.hash();
+    program.strip();
+    assert_eq!(program.hash(), hash);
+    program.ops[0].operands = Operands::One(Data::Int(16));
+    assert_ne!(program.hash(), hash);
+}
+
+fn op(op_code: OpCode, operand: Option<i64>) -> Instruction {
+    Instruction {
+        op_code,
+        operands: match operand {
+            Some(n) => Operands::One(Data::Int(n)),
+            None => Operands::Zero,
+        },
+    }
+}
+
+#[test]
+fn format_instruction() {
+    let mut program = Program {
+        ops: vec![
+            op(OpCode::Jump, Some(1)),
+            op(OpCode::CallNative, Some(0)),
+            op(OpCode::Call, Some(7)),
+        ],
+        imports: vec![Import {
+            name: "log".to_string(),
+            arity: 1,
+        }],
+        debug: Some(DebugInfo {
+            labels: vec![Label {
+                name: "next".to_string(),
+                ip: 1,
+            }],
+            ..Default::default()
+        }),
+    };
+    let text: Vec<String> = program
+        .ops
+        .iter()
+        .map(|instr| program.format_instruction(instr))
+        .collect();
+    assert_eq!(text, ["jump next", "callnative log", "call 7"]);
+    program.strip();
+    assert_eq!(program.format_instruction(&program.ops[0]), "jump 1");
+}
+
+fn verify_ops(ops: Vec<Instruction>) -> Result<VerifiedProgram, Vec<VerifyError>> {
+    verify(&Program {
+        ops,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn verify_accepts_balanced_loop() {
+    // Counts down from 3 with a function printing each value.
+    let ops = vec![
+        op(OpCode::PushInt, Some(3)),
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::Pop, None),
+        op(OpCode::Dup, None),
+        op(OpCode::Call, Some(10)),
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::Sub, None),
+        op(OpCode::Dup, None),
+        op(OpCode::CJump, Some(2)),
+        op(OpCode::Exit, None),
+        op(OpCode::Print, None),
+        op(OpCode::Ret, None),
+    ];
+    assert!(verify_ops(ops).is_ok());
+}
+
+#[test]
+fn verify_reports_errors() {
+    let errors = verify_ops(vec![
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::Add, None),
+        op(OpCode::Jump, Some(40)),
+        op(OpCode::CallNative, Some(0)),
+    ])
+    .unwrap_err();
+    let kinds: Vec<_> = errors.iter().map(|e| (e.ip, e.kind.clone())).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (1, VerifyErrorKind::StackUnderflow),
+            (2, VerifyErrorKind::TargetOutOfRange(40)),
+            (3, VerifyErrorKind::UnknownImport(0)),
+        ]
+    );
+    assert_eq!(errors[1].to_string(), "ip 2: target 40 is out of range");
+
+    // The loop head is reached with one value first and two after an iteration.
+    let errors = verify_ops(vec![
+        op(OpCode::PushInt, Some(3)),
+        op(OpCode::Dup, None),
+        op(OpCode::CJump, Some(1)),
+    ])
+    .unwrap_err();
+    assert_eq!(
+        errors[0].kind,
+        VerifyErrorKind::StackDepthMismatch {
+            expected: 1,
+            found: 2
+        }
+    );
+
+    let mut ops = vec![
+        op(OpCode::PushInt, Some(1)),
+        Instruction {
+            op_code: OpCode::PushFloat,
+            operands: Operands::One(Data::Float(Float::ONE)),
+        },
+        op(OpCode::Add, None),
+        op(OpCode::Ret, None),
+    ];
+    let errors = verify_ops(ops.clone()).unwrap_err();
+    assert_eq!(
+        errors[0].kind,
+        VerifyErrorKind::TypeMismatch(vec!["Int", "Float"])
+    );
+    ops[2] = op(OpCode::Eq, None);
+    let errors = verify_ops(ops).unwrap_err();
+    assert_eq!(errors[0].kind, VerifyErrorKind::RetOutsideFunction);
+}
+
+#[test]
+fn verify_uses_call_summaries() {
+    // `f` consumes two values and returns one.
+    let f = vec![op(OpCode::Add, None), op(OpCode::Ret, None)];
+    let mut ops = vec![
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::PushInt, Some(2)),
+        op(OpCode::Call, Some(5)),
+        op(OpCode::Print, None),
+        op(OpCode::Exit, None),
+    ];
+    ops.extend(f.clone());
+    assert!(verify_ops(ops.clone()).is_ok());
+
+    ops[3] = op(OpCode::Add, None);
+    let errors = verify_ops(ops).unwrap_err();
+    assert_eq!(errors[0].ip, 3);
+    assert_eq!(errors[0].kind, VerifyErrorKind::StackUnderflow);
+
+    // Only one argument for `f`.
+    let mut ops = vec![
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::Call, Some(3)),
+        op(OpCode::Exit, None),
+    ];
+    ops.extend(f);
+    let errors = verify_ops(ops).unwrap_err();
+    assert_eq!((errors[0].ip, &errors[0].kind), (1, &VerifyErrorKind::StackUnderflow));
+}
+
+#[test]
+fn cfg_edges_and_dominators() {
+    let ops = vec![
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::CJump, Some(4)),
+        op(OpCode::PushInt, Some(2)),
+        op(OpCode::Jump, Some(5)),
+        op(OpCode::PushInt, Some(3)),
+        op(OpCode::Call, Some(8)),
+        op(OpCode::Exit, None),
+        op(OpCode::Print, None),
+        op(OpCode::Print, None),
+        op(OpCode::Ret, None),
+    ];
+    let cfg = cfg::build(&Program {
+        ops,
+        ..Default::default()
+    });
+    let starts: Vec<_> = cfg.blocks.iter().map(|block| block.start).collect();
+    assert_eq!(starts, vec![0, 2, 4, 5, 7, 8]);
+    assert_eq!(cfg.blocks[0].successors, vec![1, 2]);
+    assert_eq!(cfg.blocks[3].predecessors, vec![1, 2]);
+    assert_eq!(cfg.blocks[5].predecessors, vec![4]);
+
+    assert_eq!(cfg.immediate_dominator(0), None);
+    assert_eq!(cfg.immediate_dominator(1), Some(0));
+    assert_eq!(cfg.immediate_dominator(3), Some(0));
+    // The function at 8 is entered through the call, and 7 is unreachable.
+    assert_eq!(cfg.immediate_dominator(5), None);
+    assert_eq!(cfg.immediate_dominator(4), None);
+    assert!(cfg.dominates(0, 3));
+    assert!(cfg.dominates(3, 3));
+    assert!(!cfg.dominates(1, 3));
+
+    // A loop header dominates its body, not the other way around.
+    let ops = vec![
+        op(OpCode::PushInt, Some(3)),
+        op(OpCode::PushInt, Some(1)),
+        op(OpCode::Sub, None),
+        op(OpCode::Dup, None),
+        op(OpCode::CJump, Some(1)),
+        op(OpCode::Exit, None),
+    ];
+    let cfg = cfg::build(&Program {
+        ops,
+        ..Default::default()
+    });
+    assert_eq!(cfg.blocks[1].predecessors, vec![0, 1]);
+    assert_eq!(cfg.immediate_dominator(2), Some(1));
+    assert!(!cfg.dominates(2, 1));
+}
@@ -0,0 +1,539 @@
+//! Static checks run before a program is executed.
+//!
+//! Each function (the program start and every `call` target) is checked by
+//! abstract interpretation over its basic blocks: the verifier tracks the
+//! depth of the operand stack and the types of values on it, and reports
+//! anything that would fail at runtime on some path.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::cfg::{successors, Cfg};
+use crate::{Data, OpCode, Operands, Program};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VerifyErrorKind {
+    /// A jump or call refers to an address outside the program.
+    TargetOutOfRange(i64),
+    /// The instruction needs an operand it doesn't have.
+    MissingOperand,
+    UnknownImport(i64),
+    /// The instruction pops more values than the stack holds.
+    StackUnderflow,
+    /// Paths reaching the instruction leave different numbers of values on the stack.
+    StackDepthMismatch {
+        expected: i64,
+        found: i64,
+    },
+    /// Operand types the instruction fails on, deepest first.
+    TypeMismatch(Vec<&'static str>),
+    /// `ret` reachable from the top level, where there is no frame to return from.
+    RetOutsideFunction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VerifyError {
+    pub ip: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ip {}: ", self.ip)?;
+        match &self.kind {
+            VerifyErrorKind::TargetOutOfRange(target) => {
+                write!(f, "target {target} is out of range")
+            }
+            VerifyErrorKind::MissingOperand => write!(f, "missing operand"),
+            VerifyErrorKind::UnknownImport(index) => write!(f, "unknown import {index}"),
+            VerifyErrorKind::StackUnderflow => write!(f, "not enough values on the stack"),
+            VerifyErrorKind::StackDepthMismatch { expected, found } => {
+                write!(
+                    f,
+                    "stack depth is {found} on one path and {expected} on another"
+                )
+            }
+            VerifyErrorKind::TypeMismatch(types) => {
+                write!(f, "incompatible operand types {}", types.join(" and "))
+            }
+            VerifyErrorKind::RetOutsideFunction => write!(f, "ret outside of a function"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// A program that passed `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedProgram {
+    program: Program,
+    underflow_free: bool,
+}
+
+impl VerifiedProgram {
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn into_program(self) -> Program {
+        self.program
+    }
+
+    /// Whether the stack depth was known on every path, which rules out
+    /// stack underflow. It isn't after `readline` or recursive calls.
+    pub fn underflow_free(&self) -> bool {
+        self.underflow_free
+    }
+}
+
+/// Checks jump and call targets, imports, stack depth at merge points and
+/// operand types where they are statically known.
+pub fn verify(program: &Program) -> Result<VerifiedProgram, Vec<VerifyError>> {
+    let mut verifier = Verifier {
+        program,
+        cfg: Cfg::build(program),
+        summaries: HashMap::new(),
+        in_progress: HashSet::new(),
+        errors: Vec::new(),
+        underflow_free: true,
+    };
+    verifier.check_operands();
+    if !program.ops.is_empty() {
+        verifier.analyze(0, true);
+    }
+    let mut errors = verifier.errors;
+    if errors.is_empty() {
+        return Ok(VerifiedProgram {
+            program: program.clone(),
+            underflow_free: verifier.underflow_free,
+        });
+    }
+    errors.sort();
+    errors.dedup();
+    Err(errors)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Float,
+    Unknown,
+}
+
+impl Ty {
+    fn of(data: &Data) -> Self {
+        match data {
+            Data::Int(_) => Ty::Int,
+            Data::Float(_) => Ty::Float,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Int => "Int",
+            Ty::Float => "Float",
+            Ty::Unknown => "Unknown",
+        }
+    }
+
+    fn join(self, other: Ty) -> Ty {
+        if self == other {
+            self
+        } else {
+            Ty::Unknown
+        }
+    }
+}
+
+/// Abstract operand stack. `depth` is relative to the stack at function
+/// entry; it isn't tracked (`exact == false`) after instructions that push a
+/// variable number of values. `types` describes the topmost slots, anything
+/// below them is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    depth: i64,
+    exact: bool,
+    types: Vec<Ty>,
+}
+
+impl State {
+    fn entry() -> Self {
+        Self {
+            depth: 0,
+            exact: true,
+            types: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, ty: Ty) {
+        self.depth += 1;
+        self.types.push(ty);
+    }
+
+    fn pop(&mut self) -> Ty {
+        self.depth -= 1;
+        self.types.pop().unwrap_or(Ty::Unknown)
+    }
+
+    fn peek(&self) -> Ty {
+        self.types.last().copied().unwrap_or(Ty::Unknown)
+    }
+
+    /// State at a point reached by both `self` and `other`.
+    fn merge(&self, other: &State) -> Result<State, VerifyErrorKind> {
+        if self.exact && other.exact && self.depth != other.depth {
+            return Err(VerifyErrorKind::StackDepthMismatch {
+                expected: self.depth,
+                found: other.depth,
+            });
+        }
+        let n = self.types.len().min(other.types.len());
+        let types = self.types[self.types.len() - n..]
+            .iter()
+            .zip(&other.types[other.types.len() - n..])
+            .map(|(a, b)| a.join(*b))
+            .collect();
+        Ok(State {
+            depth: self.depth,
+            exact: self.exact && other.exact,
+            types,
+        })
+    }
+}
+
+/// Stack effect of a function, from its entry to its `ret`s.
+#[derive(Debug, Clone)]
+struct Summary {
+    /// Caller values the function pops.
+    needs: i64,
+    /// Stack at `ret`, relative to the stack at entry.
+    ret: State,
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    cfg: Cfg,
+    /// `None` for functions that never return.
+    summaries: HashMap<usize, Option<Summary>>,
+    in_progress: HashSet<usize>,
+    errors: Vec<VerifyError>,
+    /// Cleared once any path loses track of the stack depth.
+    underflow_free: bool,
+}
+
+/// How a function's analysis treats values below its entry stack.
+struct Function {
+    top_level: bool,
+    /// Lowest depth reached, i.e. minus the number of caller values used.
+    min_depth: i64,
+    ret: Option<State>,
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, ip: usize, kind: VerifyErrorKind) {
+        self.errors.push(VerifyError { ip, kind });
+    }
+
+    /// Checks operands of every instruction, reachable or not.
+    fn check_operands(&mut self) {
+        let len = self.program.ops.len() as i64;
+        for (ip, instr) in self.program.ops.iter().enumerate() {
+            let operand = match instr.operands {
+                Operands::One(data) => data,
+                Operands::Zero => {
+                    let needs_operand = matches!(
+                        instr.op_code,
+                        OpCode::PushInt
+                            | OpCode::PushFloat
+                            | OpCode::Jump
+                            | OpCode::CJump
+                            | OpCode::Call
+                            | OpCode::CallNative
+                    );
+                    if needs_operand {
+                        self.error(ip, VerifyErrorKind::MissingOperand);
+                    }
+                    continue;
+                }
+            };
+            match (instr.op_code, operand) {
+                (OpCode::Jump | OpCode::CJump, Data::Int(target)) if target < 0 || target > len => {
+                    self.error(ip, VerifyErrorKind::TargetOutOfRange(target))
+                }
+                (OpCode::Call, Data::Int(target)) if target < 0 || target >= len => {
+                    self.error(ip, VerifyErrorKind::TargetOutOfRange(target))
+                }
+                (OpCode::CallNative, Data::Int(index))
+                    if index < 0 || index as usize >= self.program.imports.len() =>
+                {
+                    self.error(ip, VerifyErrorKind::UnknownImport(index))
+                }
+                (
+                    OpCode::Jump | OpCode::CJump | OpCode::Call | OpCode::CallNative,
+                    Data::Float(_),
+                ) => self.error(ip, VerifyErrorKind::MissingOperand),
+                _ => {}
+            }
+        }
+    }
+
+    /// Analyzes the function starting at `entry` and returns its stack effect.
+    fn analyze(&mut self, entry: usize, top_level: bool) -> Option<Summary> {
+        let mut function = Function {
+            top_level,
+            min_depth: 0,
+            ret: None,
+        };
+        let mut states: HashMap<usize, State> = HashMap::new();
+        let first = self.cfg.block_at(entry)?;
+        let mut worklist = vec![first];
+        states.insert(first, State::entry());
+
+        while let Some(block) = worklist.pop() {
+            let mut state = states[&block].clone();
+            let (start, end) = (self.cfg.blocks[block].start, self.cfg.blocks[block].end);
+            let mut live = true;
+            for ip in start..end {
+                if !self.instruction(ip, &mut state, &mut function) {
+                    live = false;
+                    break;
+                }
+                self.underflow_free &= state.exact;
+            }
+            if !live {
+                continue;
+            }
+            for next in successors(self.program, end - 1) {
+                let next = self.cfg.block_at(next).unwrap_or_default();
+                let merged = match states.get(&next) {
+                    Some(old) => match old.merge(&state) {
+                        Ok(merged) if merged == *old => continue,
+                        Ok(merged) => merged,
+                        Err(kind) => {
+                            self.error(self.cfg.blocks[next].start, kind);
+                            continue;
+                        }
+                    },
+                    None => state.clone(),
+                };
+                states.insert(next, merged);
+                worklist.push(next);
+            }
+        }
+
+        function.ret.map(|ret| Summary {
+            needs: -function.min_depth,
+            ret,
+        })
+    }
+
+    /// Pops a value, reporting underflow at the top level. Returns `None` if
+    /// the path can't continue.
+    fn pop(&mut self, ip: usize, state: &mut State, function: &mut Function) -> Option<Ty> {
+        if state.exact && state.depth <= 0 && function.top_level {
+            self.error(ip, VerifyErrorKind::StackUnderflow);
+            return None;
+        }
+        let ty = state.pop();
+        if state.exact {
+            function.min_depth = function.min_depth.min(state.depth);
+        }
+        Some(ty)
+    }
+
+    /// Applies the instruction at `ip` to `state`. Returns `false` when
+    /// control doesn't continue to the instruction's successors.
+    fn instruction(&mut self, ip: usize, state: &mut State, function: &mut Function) -> bool {
+        let program = self.program;
+        let instr = &program.ops[ip];
+        let mismatch = |types: &[Ty]| {
+            VerifyErrorKind::TypeMismatch(types.iter().map(|ty| ty.name()).collect())
+        };
+        macro_rules! pop {
+            () => {
+                match self.pop(ip, state, function) {
+                    Some(ty) => ty,
+                    None => return false,
+                }
+            };
+        }
+        match instr.op_code {
+            OpCode::Nop | OpCode::Jump => {}
+            OpCode::PushInt | OpCode::PushFloat => match instr.operands {
+                Operands::One(data) => state.push(Ty::of(&data)),
+                Operands::Zero => return false,
+            },
+            OpCode::Print | OpCode::PrintErr | OpCode::Pop => {
+                pop!();
+            }
+            OpCode::PrintChar => {
+                if pop!() == Ty::Float {
+                    self.error(ip, mismatch(&[Ty::Float]));
+                    return false;
+                }
+            }
+            OpCode::ReadInt | OpCode::ReadChar => state.push(Ty::Int),
+            OpCode::ReadLine => {
+                // Pushes the characters and their count, or -1 at the end of input.
+                state.exact = false;
+                state.types.clear();
+                state.push(Ty::Int);
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul => {
+                let a = pop!();
+                let b = pop!();
+                if a != b && a != Ty::Unknown && b != Ty::Unknown {
+                    self.error(ip, mismatch(&[b, a]));
+                    return false;
+                }
+                state.push(if a == Ty::Unknown { b } else { a });
+            }
+            OpCode::Eq | OpCode::Gr | OpCode::Less => {
+                pop!();
+                pop!();
+                state.push(Ty::Int);
+            }
+            OpCode::Not | OpCode::Dup | OpCode::CJump => {
+                if state.exact && state.depth <= 0 && function.top_level {
+                    self.error(ip, VerifyErrorKind::StackUnderflow);
+                    return false;
+                }
+                if state.exact {
+                    function.min_depth = function.min_depth.min(state.depth - 1);
+                }
+                let top = state.peek();
+                match instr.op_code {
+                    OpCode::Not => state.push(Ty::Int),
+                    OpCode::Dup => state.push(top),
+                    _ if top == Ty::Float => {
+                        self.error(ip, mismatch(&[Ty::Float]));
+                        return false;
+                    }
+                    _ => {}
+                }
+            }
+            OpCode::CallNative => {
+                let arity = match instr.operands {
+                    Operands::One(Data::Int(index)) => {
+                        match self.program.imports.get(index as usize) {
+                            Some(import) => import.arity,
+                            None => return false,
+                        }
+                    }
+                    _ => return false,
+                };
+                for _ in 0..arity {
+                    pop!();
+                }
+                state.push(Ty::Unknown);
+            }
+            OpCode::Call => {
+                let entry = match instr.operands {
+                    Operands::One(Data::Int(entry))
+                        if entry >= 0 && (entry as usize) < self.program.ops.len() =>
+                    {
+                        entry as usize
+                    }
+                    _ => return false,
+                };
+                return self.call(ip, entry, state, function);
+            }
+            OpCode::Ret => {
+                if function.top_level {
+                    self.error(ip, VerifyErrorKind::RetOutsideFunction);
+                    return false;
+                }
+                function.ret = Some(match &function.ret {
+                    Some(ret) => match ret.merge(state) {
+                        Ok(merged) => merged,
+                        Err(kind) => {
+                            self.error(ip, kind);
+                            return false;
+                        }
+                    },
+                    None => state.clone(),
+                });
+                return false;
+            }
+            OpCode::Alloc => {
+                if pop!() == Ty::Float {
+                    self.error(ip, mismatch(&[Ty::Float]));
+                    return false;
+                }
+                state.push(Ty::Int);
+            }
+            OpCode::Load => {
+                if pop!() == Ty::Float {
+                    self.error(ip, mismatch(&[Ty::Float]));
+                    return false;
+                }
+                state.push(Ty::Unknown);
+            }
+            OpCode::Store => {
+                let value = pop!();
+                if pop!() == Ty::Float {
+                    self.error(ip, mismatch(&[Ty::Float, value]));
+                    return false;
+                }
+            }
+            OpCode::Exit | OpCode::Panic => return false,
+        }
+        true
+    }
+
+    /// Applies the stack effect of calling `entry`.
+    fn call(
+        &mut self,
+        ip: usize,
+        entry: usize,
+        state: &mut State,
+        function: &mut Function,
+    ) -> bool {
+        let summary = if self.in_progress.contains(&entry) {
+            // Recursive call: the effect isn't known yet, so stop tracking the stack.
+            Some(Summary {
+                needs: 0,
+                ret: State {
+                    depth: 0,
+                    exact: false,
+                    types: Vec::new(),
+                },
+            })
+        } else if let Some(summary) = self.summaries.get(&entry) {
+            summary.clone()
+        } else {
+            self.in_progress.insert(entry);
+            let summary = self.analyze(entry, false);
+            self.in_progress.remove(&entry);
+            self.summaries.insert(entry, summary.clone());
+            summary
+        };
+        // A function that never returns ends the path.
+        let Some(summary) = summary else {
+            return false;
+        };
+
+        if state.exact {
+            if function.top_level && state.depth < summary.needs {
+                self.error(ip, VerifyErrorKind::StackUnderflow);
+                return false;
+            }
+            function.min_depth = function.min_depth.min(state.depth - summary.needs);
+        }
+        let keep = state.types.len().saturating_sub(summary.needs as usize);
+        state.types.truncate(keep);
+        let pushed = (summary.ret.depth + summary.needs).max(0) as usize;
+        let known = &summary.ret.types[summary.ret.types.len().saturating_sub(pushed)..];
+        if summary.ret.exact {
+            state
+                .types
+                .extend(std::iter::repeat_n(Ty::Unknown, pushed - known.len()));
+        } else {
+            state.types.clear();
+        }
+        state.types.extend_from_slice(known);
+        state.depth += summary.ret.depth;
+        state.exact &= summary.ret.exact;
+        true
+    }
+}
@@ -0,0 +1,498 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use agar_asm::Assembler;
+use agar_core::Program;
+use agar_vm::{ExitStatus, InterruptHandle, Interpreter, MemoryIo, StepResult};
+use serde_json::{json, Value};
+
+const THREAD_ID: i64 = 1;
+
+/// Variable references handed out by `scopes`. Locals use `LOCALS_REF + frame id`.
+const STACK_REF: i64 = 1;
+const HEAP_REF: i64 = 2;
+const LOCALS_REF: i64 = 1000;
+
+/// Instructions run between checks for new requests while the program runs.
+const SLICE_STEPS: usize = 1024;
+
+/// Reads one `Content-Length` framed message. Returns `None` at the end of input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| invalid("Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|_| invalid("Message is not valid JSON"))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn is_source(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "aa")
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// How far a step request should run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepKind {
+    Continue,
+    In,
+    Over,
+    Out,
+}
+
+/// A step or continue in progress.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    kind: StepKind,
+    /// Call depth and line where it started; `None` without line info.
+    depth: usize,
+    line: Option<usize>,
+}
+
+/// Debug Adapter Protocol session driving an `Interpreter`.
+pub struct Session<W: Write> {
+    output: W,
+    seq: i64,
+    vm: Interpreter,
+    io: MemoryIo,
+    interrupt: InterruptHandle,
+    source_path: String,
+    breakpoints: Vec<usize>,
+    stop_on_entry: bool,
+    running: Option<Run>,
+    finished: bool,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(output: W) -> Self {
+        let vm = Interpreter::new();
+        Self {
+            output,
+            seq: 1,
+            interrupt: vm.interrupt_handle(),
+            vm,
+            io: MemoryIo::new(),
+            source_path: String::new(),
+            breakpoints: Vec::new(),
+            stop_on_entry: false,
+            running: None,
+            finished: false,
+        }
+    }
+
+    /// Serves requests from `input` until `disconnect` or the end of input.
+    /// Requests are read on another thread, so they are handled while the
+    /// program runs.
+    pub fn serve<R: BufRead + Send + 'static>(&mut self, input: R) -> io::Result<()> {
+        let requests = spawn_reader(input);
+        loop {
+            let message = if self.running.is_some() {
+                match requests.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(()),
+                }
+            };
+            if !self.handle(&message?)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// Handles one request. Returns `false` when the session is over.
+    pub fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let args = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsSingleThreadExecutionRequests": false,
+                    }),
+                )?;
+            }
+            // Breakpoints can only be placed once the program is loaded, so
+            // configuration starts after `launch`.
+            "launch" => match self.launch(args) {
+                Ok(()) => {
+                    self.respond(request, json!({}))?;
+                    self.event("initialized", json!({}))?;
+                }
+                Err(message) => self.fail(request, &message)?,
+            },
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(args);
+                self.respond(request, body)?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, json!({ "breakpoints": [] }))?,
+            "configurationDone" => {
+                self.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else {
+                    self.resume(StepKind::Continue)?;
+                }
+            }
+            "threads" => self.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            )?,
+            "stackTrace" => {
+                let frames = self.stack_trace();
+                let total = frames.len();
+                self.respond(request, json!({ "stackFrames": frames, "totalFrames": total }))?;
+            }
+            "scopes" => {
+                let frame_id = args["frameId"].as_i64().unwrap_or(0);
+                self.respond(
+                    request,
+                    json!({ "scopes": [
+                        { "name": "Locals", "variablesReference": LOCALS_REF + frame_id, "expensive": false },
+                        { "name": "Operand Stack", "variablesReference": STACK_REF, "expensive": false },
+                        { "name": "Heap", "variablesReference": HEAP_REF, "expensive": false },
+                    ]}),
+                )?;
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_i64().unwrap_or(0);
+                let variables = self.variables(reference);
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            "continue" => {
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                self.resume(StepKind::Continue)?;
+            }
+            "next" => {
+                self.respond(request, json!({}))?;
+                self.resume(StepKind::Over)?;
+            }
+            "stepIn" => {
+                self.respond(request, json!({}))?;
+                self.resume(StepKind::In)?;
+            }
+            "stepOut" => {
+                self.respond(request, json!({}))?;
+                self.resume(StepKind::Out)?;
+            }
+            "pause" => {
+                self.respond(request, json!({}))?;
+                if self.running.is_some() {
+                    self.interrupt.interrupt();
+                }
+            }
+            "disconnect" => {
+                self.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            _ => self.fail(request, "Unsupported request")?,
+        }
+        Ok(true)
+    }
+
+    /// Loads a `.ab` bytecode file, or assembles a `.aa` source file.
+    fn launch(&mut self, args: &Value) -> Result<(), String> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("Missing program argument")?
+            .to_string();
+        let program = if is_source(&path) {
+            let source = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            let mut asm = Assembler::new(source);
+            asm.file = path.clone();
+            match asm.parse_source() {
+                Some(program) => program,
+                None => return Err(asm.error.unwrap_or("Can't assemble program".to_string())),
+            }
+        } else {
+            let bytecode = std::fs::read(&path).map_err(|e| e.to_string())?;
+            Program::from_bytes(&bytecode)?
+        };
+        self.source_path = match &program.debug {
+            Some(debug) => debug.file.clone(),
+            None => path,
+        };
+        self.vm.load_program(program).map_err(|e| format!("{e:?}"))?;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.breakpoints.clear();
+        let mut verified = Vec::new();
+        let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        for (id, breakpoint) in lines.iter().enumerate() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let ip = self
+                .vm
+                .program
+                .debug
+                .as_ref()
+                .and_then(|debug| debug.ip_for_line(line));
+            if let Some(ip) = ip {
+                self.breakpoints.push(ip);
+            }
+            verified.push(json!({ "id": id + 1, "verified": ip.is_some(), "line": line }));
+        }
+        json!({ "breakpoints": verified })
+    }
+
+    fn line(&self, ip: usize) -> Option<usize> {
+        let debug = self.vm.program.debug.as_ref()?;
+        debug.location(ip).map(|location| location.line)
+    }
+
+    /// Frame 0 is the current instruction, frame `n` is the `n`th caller.
+    fn stack_trace(&self) -> Vec<Value> {
+        let frames = &self.vm.frames;
+        let mut result = Vec::new();
+        for id in 0..=frames.len() {
+            let (ip, entry) = if id == 0 {
+                (self.vm.ip, frames.last().map(|frame| frame.entry))
+            } else {
+                let callee = &frames[frames.len() - id];
+                let caller = frames.len().checked_sub(id + 1).map(|i| frames[i].entry);
+                (callee.return_ip.saturating_sub(1), caller)
+            };
+            let instr = self
+                .vm
+                .program
+                .get(ip)
+                .map(|instr| self.vm.program.format_instruction(instr))
+                .unwrap_or_default();
+            result.push(json!({
+                "id": id,
+                "name": format!("{} ({})", self.vm.function_name(entry), instr),
+                "source": { "path": self.source_path },
+                "line": self.line(ip).unwrap_or(0),
+                "column": 1,
+                "instructionPointerReference": ip.to_string(),
+            }));
+        }
+        result
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let slot = |(i, data): (usize, &agar_core::Data)| {
+            json!({
+                "name": format!("[{i}]"),
+                "value": data.to_string(),
+                "type": data.type_name(),
+                "variablesReference": 0,
+            })
+        };
+        match reference {
+            STACK_REF => self.vm.stack.iter().enumerate().rev().map(slot).collect(),
+            HEAP_REF => self.vm.heap.iter().enumerate().map(slot).collect(),
+            _ => {
+                let frame_id = (reference - LOCALS_REF).max(0) as usize;
+                let frames = &self.vm.frames;
+                let (base, end) = match frame_id {
+                    0 => (frames.last().map_or(0, |f| f.stack_base), self.vm.stack.len()),
+                    n if n <= frames.len() => {
+                        let callee = &frames[frames.len() - n];
+                        let base = frames
+                            .len()
+                            .checked_sub(n + 1)
+                            .map_or(0, |i| frames[i].stack_base);
+                        (base, callee.stack_base)
+                    }
+                    _ => (0, 0),
+                };
+                let end = end.min(self.vm.stack.len());
+                self.vm.stack[base.min(end)..end]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, data)| slot((base + i, data)))
+                    .rev()
+                    .collect()
+            }
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        for (category, bytes) in [
+            ("stdout", std::mem::take(&mut self.io.stdout)),
+            ("stderr", std::mem::take(&mut self.io.stderr)),
+        ] {
+            if !bytes.is_empty() {
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                self.event("output", json!({ "category": category, "output": text }))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.flush_output()?;
+        self.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+                "text": text,
+            }),
+        )
+    }
+
+    fn terminate(&mut self, status: ExitStatus) -> io::Result<()> {
+        self.finished = true;
+        if let ExitStatus::Error(e) = &status {
+            self.io.stderr.extend_from_slice(format!("RuntimeError: {e}\n").as_bytes());
+        }
+        self.flush_output()?;
+        let code = if status == ExitStatus::Ok { 0 } else { 1 };
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    /// Starts running until the step is complete, a breakpoint is hit, the
+    /// program ends or it is paused.
+    fn resume(&mut self, kind: StepKind) -> io::Result<()> {
+        if self.finished {
+            return self.event("terminated", json!({}));
+        }
+        self.running = Some(Run {
+            kind,
+            depth: self.vm.frames.len(),
+            line: self.line(self.vm.ip),
+        });
+        Ok(())
+    }
+
+    /// Runs at most `SLICE_STEPS` instructions of the current run.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let run = match self.running {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+        for _ in 0..SLICE_STEPS {
+            if self.interrupt.take() {
+                self.running = None;
+                return self.stopped("pause", None);
+            }
+            let status = match self.vm.step(&mut self.io) {
+                StepResult::Ok => None,
+                StepResult::Exit => Some(ExitStatus::Ok),
+                StepResult::Error(e) => Some(ExitStatus::Error(e)),
+                StepResult::Panic(e) => {
+                    self.vm.panic(&mut self.io, e);
+                    Some(ExitStatus::Panic)
+                }
+                StepResult::OutOfFuel => Some(ExitStatus::OutOfFuel),
+            };
+            if let Some(status) = status {
+                self.running = None;
+                return self.terminate(status);
+            }
+
+            if self.breakpoints.contains(&self.vm.ip) {
+                self.running = None;
+                return self.stopped("breakpoint", None);
+            }
+            let new_depth = self.vm.frames.len();
+            // Without line info, such as in stripped bytecode, every
+            // instruction is a line of its own.
+            let moved = run.line.is_none() || self.line(self.vm.ip) != run.line;
+            let done = match run.kind {
+                StepKind::Continue => false,
+                StepKind::In => moved || new_depth != run.depth,
+                StepKind::Over => new_depth < run.depth || (new_depth == run.depth && moved),
+                StepKind::Out => new_depth < run.depth,
+            };
+            if done {
+                self.running = None;
+                return self.stopped("step", None);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads messages from `input` on a new thread. The channel closes at the end
+/// of input; a read error is passed on as the last message.
+fn spawn_reader<R: BufRead + Send + 'static>(mut input: R) -> Receiver<io::Result<Value>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => break,
+            Err(e) => Err(e),
+        };
+        let failed = message.is_err();
+        if sender.send(message).is_err() || failed {
+            break;
+        }
+    });
+    receiver
+}
+
+/// Runs a session over stdio.
+pub fn run_stdio() -> io::Result<()> {
+    let mut session = Session::new(io::stdout());
+    session.serve(io::BufReader::new(io::stdin()))
+}
@@ -0,0 +1,6 @@
+fn main() {
+    if let Err(e) = agar_dap::run_stdio() {
+        eprintln!("agar_dap: {e}");
+        std::process::exit(1);
+    }
+}
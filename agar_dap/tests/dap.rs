@@ -0,0 +1,319 @@
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use agar_asm::Assembler;
+use agar_dap::{read_message, write_message};
+use serde_json::{json, Value};
+
+const PROGRAM: &str = "        pushi 2
+loop:   dup
+        call show
+        pushi 1
+        sub
+        dup
+        cjump loop
+        exit
+show:   dup
+        print
+        ret
+";
+
+const ENDLESS: &str = "loop:   jump loop\n";
+
+fn source_file(name: &str, source: &str) -> String {
+    let path = std::env::temp_dir().join(format!("agar_dap_{}_{name}", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// `source` assembled without debug info, like `agar_asm --strip`.
+fn stripped_file(name: &str, source: &str) -> String {
+    let mut program = Assembler::new(source.to_string()).parse_source().unwrap();
+    program.strip();
+    let path = std::env::temp_dir().join(format!("agar_dap_{}_{name}", std::process::id()));
+    std::fs::write(&path, program.to_bytes()).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// An adapter process talked to the way an editor does.
+struct Client {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    seq: usize,
+    messages: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_agar_dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Self {
+            stdin: child.stdin.take(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            seq: 0,
+            messages: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, request: &Value) {
+        let mut request = request.clone();
+        self.seq += 1;
+        request["seq"] = json!(self.seq);
+        request["type"] = json!("request");
+        write_message(self.stdin.as_mut().unwrap(), &request).unwrap();
+    }
+
+    /// Reads messages until one matches `done`.
+    fn wait(&mut self, done: impl Fn(&Value) -> bool) -> Value {
+        loop {
+            let message = read_message(&mut self.stdout)
+                .unwrap()
+                .expect("adapter closed its output");
+            self.messages.push(message.clone());
+            if done(&message) {
+                return message;
+            }
+        }
+    }
+
+    /// Closes the adapter's input and returns everything it wrote.
+    fn finish(mut self) -> Vec<Value> {
+        drop(self.stdin.take());
+        while let Some(message) = read_message(&mut self.stdout).unwrap() {
+            self.messages.push(message);
+        }
+        assert!(self.child.wait().unwrap().success());
+        self.messages
+    }
+}
+
+/// Step of a session that waits for the program to stop or end, like an
+/// editor does before asking about its state.
+fn halted() -> Value {
+    json!({ "wait": ["stopped", "terminated"] })
+}
+
+/// Starts a session like an editor: `initialize`, then `launch`, and once the
+/// adapter sends `initialized`, the configuration and the other `requests`.
+/// Returns everything the adapter wrote back.
+fn session(path: &str, stop_on_entry: bool, requests: &[Value]) -> Vec<Value> {
+    let mut client = Client::start();
+    client.send(&json!({ "command": "initialize", "arguments": { "adapterID": "agar" } }));
+    client.wait(|m| m["command"] == "initialize");
+    client.send(&json!({
+        "command": "launch",
+        "arguments": { "program": path, "stopOnEntry": stop_on_entry },
+    }));
+    if client.wait(|m| m["command"] == "launch")["success"] == true {
+        client.wait(|m| m["event"] == "initialized");
+    }
+    for request in requests {
+        match request["wait"].as_array() {
+            Some(events) => {
+                client.wait(|m| m["type"] == "event" && events.contains(&m["event"]));
+            }
+            None => client.send(request),
+        }
+    }
+    client.finish()
+}
+
+fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+    messages
+        .iter()
+        .find(|m| m["type"] == "response" && m["command"] == command)
+        .unwrap_or_else(|| panic!("no response to {command}"))
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages
+        .iter()
+        .filter(|m| m["type"] == "event" && m["event"] == event)
+        .collect()
+}
+
+#[test]
+fn runs_to_completion() {
+    let path = source_file("complete.aa", PROGRAM);
+    let requests = [
+        json!({ "command": "configurationDone" }),
+        halted(),
+        json!({ "command": "disconnect" }),
+    ];
+    let messages = session(&path, false, &requests);
+
+    assert_eq!(response(&messages, "initialize")["success"], true);
+    assert_eq!(events(&messages, "initialized").len(), 1);
+    assert_eq!(response(&messages, "launch")["success"], true);
+    let output: String = events(&messages, "output")
+        .iter()
+        .map(|e| e["body"]["output"].as_str().unwrap())
+        .collect();
+    assert_eq!(output, "21");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+}
+
+#[test]
+fn breakpoints_stack_and_variables() {
+    let path = source_file("breakpoints.aa", PROGRAM);
+    let requests = [
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": path },
+            "breakpoints": [{ "line": 10 }, { "line": 42 }],
+        }}),
+        json!({ "command": "configurationDone" }),
+        halted(),
+        json!({ "command": "threads" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1000 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "disconnect" }),
+    ];
+    let messages = session(&path, false, &requests);
+
+    let breakpoints = &response(&messages, "setBreakpoints")["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+    assert_eq!(response(&messages, "threads")["body"]["threads"][0]["id"], 1);
+
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames.as_array().unwrap().len(), 2);
+    assert_eq!(frames[0]["line"], 10);
+    assert_eq!(frames[0]["name"], "show (print)");
+    assert_eq!(frames[0]["source"]["path"], path.as_str());
+    assert_eq!(frames[1]["line"], 3);
+    assert_eq!(frames[1]["name"], "main (call show)");
+
+    let scopes = &response(&messages, "scopes")["body"]["scopes"];
+    assert_eq!(scopes[0]["name"], "Locals");
+    assert_eq!(scopes[1]["name"], "Operand Stack");
+
+    let variables: Vec<&Value> = messages
+        .iter()
+        .filter(|m| m["command"] == "variables")
+        .map(|m| &m["body"]["variables"])
+        .collect();
+    assert_eq!(variables[0], &json!([{ "name": "[2]", "value": "2", "type": "Int", "variablesReference": 0 }]));
+    assert_eq!(variables[1].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn stepping() {
+    let path = source_file("stepping.aa", PROGRAM);
+    let requests = [
+        json!({ "command": "configurationDone" }),
+        halted(),
+        json!({ "command": "next" }),
+        halted(),
+        json!({ "command": "next" }),
+        halted(),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stepIn" }),
+        halted(),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stepIn" }),
+        halted(),
+        json!({ "command": "stepOut" }),
+        halted(),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue" }),
+        halted(),
+        json!({ "command": "disconnect" }),
+    ];
+    let messages = session(&path, true, &requests);
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped[0]["body"]["reason"], "entry");
+    assert_eq!(stopped[1]["body"]["reason"], "step");
+
+    let traces: Vec<&Value> = messages
+        .iter()
+        .filter(|m| m["command"] == "stackTrace")
+        .map(|m| &m["body"]["stackFrames"])
+        .collect();
+    // Two `next`s from line 1 land on the call, without entering it.
+    assert_eq!(traces[0][0]["line"], 3);
+    assert_eq!(traces[0].as_array().unwrap().len(), 1);
+    // Stepping into the call reaches the first line of `show`.
+    assert_eq!(traces[1][0]["line"], 9);
+    assert_eq!(traces[1].as_array().unwrap().len(), 2);
+    // Stepping out returns right after the call.
+    assert_eq!(traces[2][0]["line"], 4);
+    assert_eq!(traces[2].as_array().unwrap().len(), 1);
+
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+}
+
+#[test]
+fn launch_missing_program() {
+    let messages = session("/nonexistent/program.aa", false, &[]);
+    assert_eq!(response(&messages, "launch")["success"], false);
+    assert!(events(&messages, "initialized").is_empty());
+}
+
+#[test]
+fn launch_invalid_source() {
+    let path = source_file("invalid.aa", "        pushi 1\n        frobnicate\n");
+    let messages = session(&path, false, &[]);
+    let launch = response(&messages, "launch");
+    assert_eq!(launch["success"], false);
+    assert_eq!(launch["message"], "Error at line 2: Unknown instruction");
+}
+
+#[test]
+fn stepping_without_debug_info() {
+    let path = stripped_file("stripped.ab", PROGRAM);
+    let requests = [
+        json!({ "command": "configurationDone" }),
+        halted(),
+        json!({ "command": "next" }),
+        halted(),
+        json!({ "command": "stepIn" }),
+        halted(),
+        json!({ "command": "disconnect" }),
+    ];
+    let messages = session(&path, true, &requests);
+
+    // Each step runs a single instruction.
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 3);
+    assert_eq!(stopped[2]["body"]["reason"], "step");
+    assert!(events(&messages, "output").is_empty());
+    assert!(events(&messages, "exited").is_empty());
+}
+
+#[test]
+fn pause_running_program() {
+    let path = source_file("pause.aa", ENDLESS);
+    let requests = [
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "threads" }),
+        json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+        halted(),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue" }),
+        json!({ "command": "disconnect" }),
+    ];
+    let messages = session(&path, false, &requests);
+
+    // Requests are answered while the program runs.
+    assert_eq!(response(&messages, "threads")["success"], true);
+    assert_eq!(response(&messages, "pause")["success"], true);
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "pause");
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["line"], 1);
+    assert_eq!(response(&messages, "disconnect")["success"], true);
+    assert!(events(&messages, "terminated").is_empty());
+}
@@ -36,7 +36,7 @@ pub fn jump(item: TokenStream) -> TokenStream {
         Some(TokenTree::Literal(a)) => format!(
             "Instruction {{
                 op_code: OpCode::Jump,
-                operands: Operands::One(Data::Int({a}))),
+                operands: Operands::One(Data::Int({a})),
             }}"
         )
         .parse()
@@ -51,7 +51,37 @@ pub fn cjump(item: TokenStream) -> TokenStream {
         Some(TokenTree::Literal(a)) => format!(
             "Instruction {{
                 op_code: OpCode::CJump,
-                operands: Operands::One(Data::Int({a}))),
+                operands: Operands::One(Data::Int({a})),
+            }}"
+        )
+        .parse()
+        .unwrap(),
+        _ => TokenStream::new(),
+    }
+}
+
+#[proc_macro]
+pub fn callnative(item: TokenStream) -> TokenStream {
+    match item.into_iter().next() {
+        Some(TokenTree::Literal(a)) => format!(
+            "Instruction {{
+                op_code: OpCode::CallNative,
+                operands: Operands::One(Data::Int({a})),
+            }}"
+        )
+        .parse()
+        .unwrap(),
+        _ => TokenStream::new(),
+    }
+}
+
+#[proc_macro]
+pub fn call(item: TokenStream) -> TokenStream {
+    match item.into_iter().next() {
+        Some(TokenTree::Literal(a)) => format!(
+            "Instruction {{
+                op_code: OpCode::Call,
+                operands: Operands::One(Data::Int({a})),
             }}"
         )
         .parse()
@@ -207,3 +237,93 @@ pub fn panic(_item: TokenStream) -> TokenStream {
     .parse()
     .unwrap()
 }
+
+#[proc_macro]
+pub fn printerr(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::PrintErr,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn readint(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::ReadInt,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn readchar(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::ReadChar,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn readline(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::ReadLine,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn ret(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::Ret,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn alloc(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::Alloc,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn load(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::Load,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn store(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::Store,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
+
+#[proc_macro]
+pub fn pop(_item: TokenStream) -> TokenStream {
+    "Instruction {
+        op_code: OpCode::Pop,
+        operands: Operands::Zero,
+    }"
+    .parse()
+    .unwrap()
+}
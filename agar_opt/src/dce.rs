@@ -0,0 +1,32 @@
+use agar_core::cfg::Cfg;
+use agar_core::Program;
+
+use crate::{remove_blocks, Pass};
+
+/// Deletes basic blocks that can't be reached from the entry point, following
+/// jumps, fall-through and calls.
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&self, program: &mut Program) -> usize {
+        let cfg = Cfg::build(program);
+        let reachable = cfg.reachable(program);
+
+        let mut dead = vec![false; program.ops.len()];
+        let mut changes = 0;
+        for (block, reachable) in cfg.blocks.iter().zip(&reachable) {
+            if !reachable {
+                dead[block.start..block.end].fill(true);
+                changes += 1;
+            }
+        }
+        if changes > 0 {
+            remove_blocks(program, &dead);
+        }
+        changes
+    }
+}
@@ -0,0 +1,116 @@
+use agar_core::cfg::Cfg;
+use agar_core::{Data, Instruction, OpCode, Operands, Program};
+
+use crate::{remove, Pass};
+
+/// Evaluates instructions whose operands are constants pushed right before
+/// them in the same basic block:
+///
+/// - `push a; push b; op` for `add`, `sub`, `mul`, `eq`, `gr` and `less`
+///   becomes a single push of the result;
+/// - `push a; dup` and `push a; not` push their result directly, which lets
+///   more sequences fold;
+/// - `pushi c; cjump L` becomes `pushi c; jump L` or just `pushi c`.
+///
+/// Operations that would fail at runtime, including integer overflow, are
+/// left for the VM to report.
+pub struct ConstantFolding;
+
+fn constant(instr: &Instruction) -> Option<Data> {
+    match (instr.op_code, instr.operands) {
+        (OpCode::PushInt | OpCode::PushFloat, Operands::One(data)) => Some(data),
+        _ => None,
+    }
+}
+
+fn push(data: Data) -> Instruction {
+    let op_code = match data {
+        Data::Int(_) => OpCode::PushInt,
+        Data::Float(_) => OpCode::PushFloat,
+    };
+    Instruction {
+        op_code,
+        operands: Operands::One(data),
+    }
+}
+
+/// Result of `op_code` with `b` on top of `a`, as the VM computes it.
+fn evaluate(op_code: OpCode, a: Data, b: Data) -> Option<Data> {
+    let flag = |b: bool| Some(Data::Int(b as i64));
+    match (op_code, a, b) {
+        (OpCode::Add, Data::Int(a), Data::Int(b)) => b.checked_add(a).map(Data::Int),
+        (OpCode::Sub, Data::Int(a), Data::Int(b)) => a.checked_sub(b).map(Data::Int),
+        (OpCode::Mul, Data::Int(a), Data::Int(b)) => b.checked_mul(a).map(Data::Int),
+        (OpCode::Add, Data::Float(a), Data::Float(b)) => b.checked_add(a).map(Data::Float),
+        (OpCode::Sub, Data::Float(a), Data::Float(b)) => a.checked_sub(b).map(Data::Float),
+        (OpCode::Mul, Data::Float(a), Data::Float(b)) => b.checked_mul(a).map(Data::Float),
+        (OpCode::Eq, a, b) => flag(b == a),
+        (OpCode::Gr, a, b) => flag(a > b),
+        (OpCode::Less, a, b) => flag(a < b),
+        _ => None,
+    }
+}
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, program: &mut Program) -> usize {
+        let cfg = Cfg::build(program);
+        let len = program.ops.len();
+        // A block starts wherever something jumps to, so an instruction in
+        // the same block as the one before it can only be reached from it.
+        let same_block =
+            |ip: usize, n: usize| ip + n < len && cfg.block_at(ip) == cfg.block_at(ip + n);
+        let mut dead = vec![false; len];
+        let mut changes = 0;
+        let mut ip = 0;
+        while ip < len {
+            let a = match constant(&program.ops[ip]) {
+                Some(a) if same_block(ip, 1) => a,
+                _ => {
+                    ip += 1;
+                    continue;
+                }
+            };
+            let next = &program.ops[ip + 1];
+            if let (Some(b), true) = (constant(next), same_block(ip, 2)) {
+                if let Some(result) = evaluate(program.ops[ip + 2].op_code, a, b) {
+                    program.ops[ip] = push(result);
+                    dead[ip + 1] = true;
+                    dead[ip + 2] = true;
+                    changes += 1;
+                    ip += 3;
+                    continue;
+                }
+            }
+            match (next.op_code, next.operands, a) {
+                (OpCode::Dup, _, _) => program.ops[ip + 1] = push(a),
+                (OpCode::Not, _, Data::Int(a)) => {
+                    program.ops[ip + 1] = push(Data::Int((a == 0) as i64))
+                }
+                (OpCode::Not, _, Data::Float(a)) => {
+                    program.ops[ip + 1] = push(Data::Int(a.is_zero() as i64))
+                }
+                (OpCode::CJump, Operands::One(Data::Int(_)), Data::Int(cond)) => {
+                    if cond > 0 {
+                        program.ops[ip + 1].op_code = OpCode::Jump;
+                    } else {
+                        dead[ip + 1] = true;
+                    }
+                }
+                _ => {
+                    ip += 1;
+                    continue;
+                }
+            }
+            changes += 1;
+            ip += 1;
+        }
+        if dead.contains(&true) {
+            remove(program, &dead);
+        }
+        changes
+    }
+}
@@ -0,0 +1,20 @@
+//! Bytecode optimizer: passes that rewrite a `Program` without changing what
+//! it does, and a pass manager that runs them.
+//!
+//! Passes assume the program doesn't underflow the stack; they may change how
+//! a program that does fails.
+
+mod dce;
+mod fold;
+mod pass;
+mod peephole;
+mod rewrite;
+
+pub use crate::dce::*;
+pub use crate::fold::*;
+pub use crate::pass::*;
+pub use crate::peephole::*;
+pub use crate::rewrite::*;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,68 @@
+use agar_core::Program;
+
+use crate::{ConstantFolding, DeadCode, JumpThreading, Peephole};
+
+/// A rewrite of the whole program.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    /// Rewrites `program` and returns how many changes were made.
+    fn run(&self, program: &mut Program) -> usize;
+}
+
+/// Upper bound on rounds, in case passes keep undoing each other.
+const MAX_ROUNDS: usize = 16;
+
+/// Runs passes in order, repeating the whole sequence while any of them
+/// still changes the program.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The passes behind `agar_asm -O`.
+    pub fn standard() -> Self {
+        let mut manager = Self::new();
+        manager.add(ConstantFolding);
+        manager.add(Peephole);
+        manager.add(JumpThreading);
+        manager.add(DeadCode);
+        manager
+    }
+
+    pub fn add<P: Pass + 'static>(&mut self, pass: P) {
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.passes.iter().map(|pass| pass.name())
+    }
+
+    /// Optimizes `program` until nothing changes, returning the number of
+    /// changes made by each pass.
+    pub fn run(&self, program: &mut Program) -> Vec<(&'static str, usize)> {
+        let mut changes: Vec<_> = self.passes().map(|name| (name, 0)).collect();
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for (pass, count) in self.passes.iter().zip(changes.iter_mut()) {
+                let n = pass.run(program);
+                count.1 += n;
+                changed |= n > 0;
+            }
+            if !changed {
+                break;
+            }
+        }
+        changes
+    }
+}
+
+/// Runs the standard passes on `program`.
+pub fn optimize(program: &mut Program) {
+    PassManager::standard().run(program);
+}
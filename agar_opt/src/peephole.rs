@@ -0,0 +1,112 @@
+use agar_core::{Data, Instruction, OpCode, Operands, Program};
+
+use crate::{jump_targets, remove, Pass};
+
+/// Deletes instruction sequences that have no effect:
+///
+/// - `nop` and a `jump` to the next instruction;
+/// - a push, `dup` or `not` immediately discarded with `pop`;
+/// - `not; not; pop`, which leaves the same as a single `not`.
+///
+/// Sequences that something jumps into the middle of are left alone.
+pub struct Peephole;
+
+/// Instructions to delete for a sequence starting at `ip`, as `(skip, count)`:
+/// `count` instructions after the first `skip`.
+fn rule(ops: &[Instruction], ip: usize) -> Option<(usize, usize)> {
+    let op_code = |n: usize| ops.get(ip + n).map(|instr| instr.op_code);
+    match (op_code(0)?, op_code(1), op_code(2)) {
+        (OpCode::Nop, _, _) => Some((0, 1)),
+        (OpCode::Jump, _, _) if ops[ip].operands == Operands::One(Data::Int(ip as i64 + 1)) => {
+            Some((0, 1))
+        }
+        (OpCode::Not, Some(OpCode::Not), Some(OpCode::Pop)) => Some((1, 2)),
+        (OpCode::PushInt | OpCode::PushFloat | OpCode::Dup | OpCode::Not, Some(OpCode::Pop), _) => {
+            Some((0, 2))
+        }
+        _ => None,
+    }
+}
+
+impl Pass for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+
+    fn run(&self, program: &mut Program) -> usize {
+        let targets = jump_targets(program);
+        let mut dead = vec![false; program.ops.len()];
+        let mut changes = 0;
+        let mut ip = 0;
+        while ip < program.ops.len() {
+            match rule(&program.ops, ip) {
+                Some((skip, count)) if !targets[ip + 1..ip + skip + count].contains(&true) => {
+                    dead[ip + skip..ip + skip + count].fill(true);
+                    changes += 1;
+                    ip += skip + count;
+                }
+                _ => ip += 1,
+            }
+        }
+        if changes > 0 {
+            remove(program, &dead);
+        }
+        changes
+    }
+}
+
+/// Points jumps straight at the end of a chain of jumps. A `cjump` can also
+/// go through other `cjump`s, since it leaves its condition on the stack. A
+/// `jump` to `exit` or `ret` becomes that instruction.
+pub struct JumpThreading;
+
+impl JumpThreading {
+    /// Where a jump to `target` ends up after following unconditional jumps,
+    /// and conditional ones too if `conditional`.
+    fn follow(ops: &[Instruction], mut target: i64, conditional: bool) -> i64 {
+        for _ in 0..ops.len() {
+            let next = match usize::try_from(target).ok().and_then(|ip| ops.get(ip)) {
+                Some(instr) => instr,
+                None => break,
+            };
+            match (next.op_code, next.operands) {
+                (OpCode::Jump, Operands::One(Data::Int(next))) => target = next,
+                (OpCode::CJump, Operands::One(Data::Int(next))) if conditional => target = next,
+                _ => break,
+            }
+        }
+        target
+    }
+}
+
+impl Pass for JumpThreading {
+    fn name(&self) -> &'static str {
+        "jump-threading"
+    }
+
+    fn run(&self, program: &mut Program) -> usize {
+        let mut changes = 0;
+        for ip in 0..program.ops.len() {
+            let instr = &program.ops[ip];
+            let target = match (instr.op_code, instr.operands) {
+                (OpCode::Jump | OpCode::CJump, Operands::One(Data::Int(target))) => target,
+                _ => continue,
+            };
+            let conditional = instr.op_code == OpCode::CJump;
+            let threaded = Self::follow(&program.ops, target, conditional);
+            let end = usize::try_from(threaded)
+                .ok()
+                .and_then(|ip| program.ops.get(ip))
+                .map(|instr| instr.op_code);
+            if !conditional && matches!(end, Some(OpCode::Exit | OpCode::Ret)) {
+                program.ops[ip].op_code = end.unwrap();
+                program.ops[ip].operands = Operands::Zero;
+                changes += 1;
+            } else if threaded != target {
+                program.ops[ip].operands = Operands::One(Data::Int(threaded));
+                changes += 1;
+            }
+        }
+        changes
+    }
+}
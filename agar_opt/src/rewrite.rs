@@ -0,0 +1,93 @@
+use agar_core::{Data, Instruction, OpCode, Operands, Program};
+
+/// Whether `instr`'s operand is an instruction address.
+pub fn has_address(instr: &Instruction) -> bool {
+    matches!(instr.op_code, OpCode::Jump | OpCode::CJump | OpCode::Call)
+}
+
+/// In-range target of a jump or call.
+pub fn target(instr: &Instruction, len: usize) -> Option<usize> {
+    match instr.operands {
+        Operands::One(Data::Int(ip)) if has_address(instr) && ip >= 0 && (ip as usize) < len => {
+            Some(ip as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Marks every instruction some jump or call can land on.
+pub fn jump_targets(program: &Program) -> Vec<bool> {
+    let len = program.ops.len();
+    let mut targets = vec![false; len];
+    for instr in &program.ops {
+        if let Some(ip) = target(instr, len) {
+            targets[ip] = true;
+        }
+    }
+    targets
+}
+
+/// Deletes the instructions marked in `dead` and compacts the program.
+/// Jumps, calls, labels and the line table are updated; an address of a
+/// deleted instruction moves to the next one that's kept. Meant for
+/// instructions a rewrite made redundant, whose labels still name the code
+/// that follows.
+pub fn remove(program: &mut Program, dead: &[bool]) {
+    compact(program, dead, false);
+}
+
+/// Deletes unreachable code marked in `dead`, like `remove`, except that
+/// labels inside it are deleted too instead of naming whatever code follows.
+pub fn remove_blocks(program: &mut Program, dead: &[bool]) {
+    compact(program, dead, true);
+}
+
+fn compact(program: &mut Program, dead: &[bool], drop_labels: bool) {
+    let len = program.ops.len();
+    // new_ip[ip] is where `ip` ends up, or where the next kept instruction does.
+    let mut new_ip = Vec::with_capacity(len + 1);
+    let mut next = 0;
+    for is_dead in &dead[..len] {
+        new_ip.push(next);
+        if !is_dead {
+            next += 1;
+        }
+    }
+    new_ip.push(next);
+    let removed = len - next;
+    let relocate = |ip: i64| -> i64 {
+        match usize::try_from(ip) {
+            Ok(ip) if ip <= len => new_ip[ip] as i64,
+            // Past the end stays past the end.
+            Ok(_) => ip - removed as i64,
+            Err(_) => ip,
+        }
+    };
+
+    let ops = std::mem::take(&mut program.ops);
+    for (ip, mut instr) in ops.into_iter().enumerate() {
+        if dead[ip] {
+            continue;
+        }
+        if let (true, Operands::One(Data::Int(address))) = (has_address(&instr), instr.operands) {
+            instr.operands = Operands::One(Data::Int(relocate(address)));
+        }
+        program.ops.push(instr);
+    }
+
+    if let Some(debug) = &mut program.debug {
+        let mut ip = 0;
+        debug.locations.retain(|_| {
+            ip += 1;
+            !dead.get(ip - 1).copied().unwrap_or(false)
+        });
+        if drop_labels {
+            debug
+                .labels
+                .retain(|label| !dead.get(label.ip).copied().unwrap_or(false));
+        }
+        for label in debug.labels.iter_mut() {
+            label.ip = relocate(label.ip as i64) as usize;
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use agar_core::*;
+use agar_macro::print as prnt;
+use agar_macro::*;
+
+use crate::*;
+
+fn from_ops(ops: Vec<Instruction>) -> Program {
+    Program {
+        ops,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn removes_no_ops() {
+    let mut program = from_ops(vec![
+        nop!(),
+        pushi!(1),
+        pushi!(2),
+        pop!(),
+        dup!(),
+        pop!(),
+        jump!(7),
+        not!(),
+        not!(),
+        pop!(),
+        prnt!(),
+    ]);
+    assert_eq!(Peephole.run(&mut program), 5);
+    assert_eq!(program.ops, vec![pushi!(1), not!(), prnt!()]);
+}
+
+#[test]
+fn keeps_sequences_entered_in_the_middle() {
+    // `pop` is a jump target, so `pushi 2; pop` isn't dead.
+    let mut program = from_ops(vec![pushi!(1), jump!(3), pushi!(2), pop!(), prnt!()]);
+    assert_eq!(Peephole.run(&mut program), 0);
+    assert_eq!(program.ops.len(), 5);
+}
+
+#[test]
+fn relocates_jumps_and_debug_info() {
+    let mut program = from_ops(vec![
+        nop!(),
+        pushi!(3),
+        nop!(),
+        dup!(),
+        prnt!(),
+        pushi!(1),
+        sub!(),
+        dup!(),
+        cjump!(2),
+        call!(11),
+        exit!(),
+        ret!(),
+    ]);
+    let locations = (1..=12).map(|line| SourceLocation { line, column: 1 });
+    program.debug = Some(DebugInfo {
+        file: "test.aa".to_string(),
+        locations: locations.collect(),
+        labels: vec![
+            Label {
+                name: "loop".to_string(),
+                ip: 2,
+            },
+            Label {
+                name: "end".to_string(),
+                ip: 12,
+            },
+        ],
+    });
+    optimize(&mut program);
+
+    assert_eq!(program.ops.len(), 10);
+    assert_eq!(program.ops[6], cjump!(1));
+    assert_eq!(program.ops[7], call!(9));
+    let debug = program.debug.unwrap();
+    assert_eq!(debug.label_address("loop"), Some(1));
+    assert_eq!(debug.label_address("end"), Some(10));
+    assert_eq!(
+        debug.location(1),
+        Some(SourceLocation { line: 4, column: 1 })
+    );
+    assert_eq!(debug.locations.len(), 10);
+}
+
+#[test]
+fn threads_jumps() {
+    let mut program = from_ops(vec![
+        jump!(2),
+        exit!(),
+        jump!(4),
+        pushi!(1),
+        jump!(6),
+        exit!(),
+        cjump!(7),
+        cjump!(9),
+        jump!(5),
+        prnt!(),
+    ]);
+    assert_eq!(JumpThreading.run(&mut program), 4);
+    // Through two jumps onto the `cjump`, where a `jump` has to stop.
+    assert_eq!(program.ops[0], jump!(6));
+    assert_eq!(program.ops[2], jump!(6));
+    // Through the `cjump` that shares the condition.
+    assert_eq!(program.ops[6], cjump!(9));
+    // A `jump` to `exit` is an `exit`.
+    assert_eq!(program.ops[8], exit!());
+
+    // A loop jumping to itself stays as it is.
+    let mut program = from_ops(vec![jump!(0)]);
+    assert_eq!(JumpThreading.run(&mut program), 0);
+}
+
+#[test]
+fn pass_manager_reaches_fixed_point() {
+    // Removing the inner pair exposes the outer one.
+    let mut program = from_ops(vec![pushi!(1), pushi!(2), pop!(), pop!(), prnt!()]);
+    let mut manager = PassManager::new();
+    manager.add(Peephole);
+    let changes = manager.run(&mut program);
+    assert_eq!(changes, vec![("peephole", 2)]);
+    assert_eq!(program.ops, vec![prnt!()]);
+}
+
+#[test]
+fn folds_constants() {
+    let mut program = from_ops(vec![
+        pushi!(2),
+        pushi!(3),
+        mul!(),
+        pushi!(10),
+        sub!(),
+        pushf!("1.5"),
+        pushf!("2.25"),
+        add!(),
+        pushi!(4),
+        pushi!(4),
+        eq!(),
+        pushi!(5),
+        pushi!(9),
+        gr!(),
+        prnt!(),
+    ]);
+    optimize(&mut program);
+    assert_eq!(
+        program.ops,
+        vec![
+            Instruction {
+                op_code: OpCode::PushInt,
+                operands: Operands::One(Data::Int(-4)),
+            },
+            pushf!("3.75"),
+            pushi!(1),
+            pushi!(0),
+            prnt!()
+        ]
+    );
+}
+
+#[test]
+fn folding_stops_at_blocks_and_failures() {
+    let ops = vec![
+        // `add` is a jump target, so its operands aren't always these.
+        pushi!(1),
+        pushi!(2),
+        add!(),
+        dup!(),
+        cjump!(2),
+        // Mixed types and overflow fail at runtime.
+        pushi!(1),
+        pushf!("1.0"),
+        add!(),
+        pushi!(9223372036854775807),
+        pushi!(1),
+        add!(),
+    ];
+    let mut program = from_ops(ops.clone());
+    assert_eq!(ConstantFolding.run(&mut program), 0);
+    assert_eq!(program.ops, ops);
+}
+
+#[test]
+fn folds_conditions() {
+    let mut program = from_ops(vec![
+        pushi!(0),
+        not!(),
+        cjump!(5),
+        pushi!(7),
+        prnt!(),
+        pushi!(0),
+        dup!(),
+        cjump!(9),
+        prnt!(),
+        exit!(),
+    ]);
+    optimize(&mut program);
+    // The first condition is always true and the second always false, which
+    // leaves `pushi 7; print` unreachable.
+    assert_eq!(
+        program.ops,
+        vec![pushi!(0), pushi!(1), pushi!(0), pushi!(0), prnt!(), exit!()]
+    );
+}
+
+#[test]
+fn removes_unreachable_code() {
+    let mut program = from_ops(vec![
+        call!(6),
+        jump!(4),
+        pushi!(1),
+        prnt!(),
+        exit!(),
+        prnt!(),
+        pushi!(2),
+        prnt!(),
+        ret!(),
+        pushi!(3),
+        ret!(),
+    ]);
+    assert_eq!(DeadCode.run(&mut program), 3);
+    assert_eq!(
+        program.ops,
+        vec![call!(3), jump!(2), exit!(), pushi!(2), prnt!(), ret!()]
+    );
+}
+
+#[test]
+fn drops_labels_of_unreachable_code() {
+    let mut program = from_ops(vec![
+        call!(4),
+        exit!(),
+        pushi!(1),
+        ret!(),
+        pushi!(2),
+        prnt!(),
+        ret!(),
+    ]);
+    let label = |name: &str, ip| Label {
+        name: name.to_string(),
+        ip,
+    };
+    program.debug = Some(DebugInfo {
+        file: "test.aa".to_string(),
+        locations: Vec::new(),
+        labels: vec![label("dead", 2), label("live", 4)],
+    });
+    assert_eq!(DeadCode.run(&mut program), 1);
+
+    let debug = program.debug.unwrap();
+    assert_eq!(debug.labels, vec![label("live", 2)]);
+    assert_eq!(debug.label_at(2), Some("live"));
+}
@@ -0,0 +1,66 @@
+//! Compares the checked interpreter with the unchecked loop used for
+//! verified programs: `cargo bench -p agar_vm`.
+
+use std::time::{Duration, Instant};
+
+use agar_asm::Assembler;
+use agar_core::verify;
+use agar_vm::{ExitStatus, Interpreter, MemoryIo};
+
+const ITERATIONS: i64 = 2_000_000;
+const RUNS: u32 = 5;
+
+fn source() -> String {
+    format!(
+        "        pushi {ITERATIONS}
+        pushi 0
+loop:   pop
+        pushi 1
+        sub
+        dup
+        pushi 3
+        mul
+        pushi 7
+        add
+        pop
+        dup
+        cjump loop
+        exit
+"
+    )
+}
+
+/// Best of `RUNS` runs, to keep noise from other processes out.
+fn measure(verified: bool) -> Duration {
+    let program = Assembler::new(source()).parse_source().unwrap();
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let mut vm = Interpreter::new();
+        if verified {
+            vm.load_verified(verify(&program).unwrap()).unwrap();
+        } else {
+            vm.load_program(program.clone()).unwrap();
+        }
+        let start = Instant::now();
+        let status = vm.run(&mut MemoryIo::new());
+        best = best.min(start.elapsed());
+        assert_eq!(status, ExitStatus::Ok);
+    }
+    best
+}
+
+fn main() {
+    let checked = measure(false);
+    let unchecked = measure(true);
+    let instructions = ITERATIONS as f64 * 11.0;
+    for (name, time) in [("checked", checked), ("verified", unchecked)] {
+        println!(
+            "{name:>10}: {time:>10.2?}  {:>7.1} Minstr/s",
+            instructions / time.as_secs_f64() / 1e6
+        );
+    }
+    println!(
+        "   speedup: {:.2}x",
+        checked.as_secs_f64() / unchecked.as_secs_f64()
+    );
+}
@@ -0,0 +1,214 @@
+use agar_core::{Data, Instruction, OpCode, Operands, Program};
+
+/// Instruction as the interpreter executes it: an opcode with at most a 32-bit
+/// operand, 8 bytes in total. Constants that don't fit live in `Code::consts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Nop,
+    /// Pushes an `Int` that fits in 32 bits.
+    PushSmall(i32),
+    /// `pushi` of `consts[index]`.
+    PushConst(u32),
+    /// `pushf` of `consts[index]`.
+    PushFloat(u32),
+    Add,
+    Sub,
+    Mul,
+    Dup,
+    Print,
+    Exit,
+    Panic,
+    PrintChar,
+    Eq,
+    Gr,
+    Less,
+    Not,
+    Jump(u32),
+    CJump(u32),
+    CallNative(u32),
+    ReadInt,
+    ReadChar,
+    ReadLine,
+    PrintErr,
+    Call(u32),
+    Ret,
+    Alloc,
+    Load,
+    Store,
+    Pop,
+    /// Instruction whose operand is missing or has the wrong type. Executing
+    /// it fails with `ErrorKind::InvalidValue`.
+    Malformed(OpCode),
+    // Superinstructions. Each replaces only the first instruction of the
+    // sequence it stands for; the others keep their own slots, so jumps into
+    // the middle of a sequence still work. See `Op::unfused`.
+    /// `pushi n; add`
+    AddImm(i32),
+    /// `pushi n; sub`
+    SubImm(i32),
+    /// `pushi 0; eq`
+    EqZero,
+    /// `pushi 0; eq; cjump target`
+    EqZeroCJump(u32),
+    /// `dup; cjump target`
+    DupCJump(u32),
+    /// `not; cjump target`
+    NotCJump(u32),
+}
+
+impl Op {
+    /// Opcode of the instruction this was lowered from.
+    pub fn op_code(&self) -> OpCode {
+        match self.unfused() {
+            Op::Nop => OpCode::Nop,
+            Op::PushSmall(_) => OpCode::PushInt,
+            Op::PushConst(_) => OpCode::PushInt,
+            Op::PushFloat(_) => OpCode::PushFloat,
+            Op::Add => OpCode::Add,
+            Op::Sub => OpCode::Sub,
+            Op::Mul => OpCode::Mul,
+            Op::Dup => OpCode::Dup,
+            Op::Print => OpCode::Print,
+            Op::Exit => OpCode::Exit,
+            Op::Panic => OpCode::Panic,
+            Op::PrintChar => OpCode::PrintChar,
+            Op::Eq => OpCode::Eq,
+            Op::Gr => OpCode::Gr,
+            Op::Less => OpCode::Less,
+            Op::Not => OpCode::Not,
+            Op::Jump(_) => OpCode::Jump,
+            Op::CJump(_) => OpCode::CJump,
+            Op::CallNative(_) => OpCode::CallNative,
+            Op::ReadInt => OpCode::ReadInt,
+            Op::ReadChar => OpCode::ReadChar,
+            Op::ReadLine => OpCode::ReadLine,
+            Op::PrintErr => OpCode::PrintErr,
+            Op::Call(_) => OpCode::Call,
+            Op::Ret => OpCode::Ret,
+            Op::Alloc => OpCode::Alloc,
+            Op::Load => OpCode::Load,
+            Op::Store => OpCode::Store,
+            Op::Pop => OpCode::Pop,
+            Op::Malformed(op_code) => op_code,
+            fused => unreachable!("{fused:?} is a superinstruction"),
+        }
+    }
+
+    /// The first instruction of a superinstruction's sequence, or `self` for
+    /// anything else. Single-stepping executes this.
+    pub fn unfused(self) -> Op {
+        match self {
+            Op::AddImm(n) | Op::SubImm(n) => Op::PushSmall(n),
+            Op::EqZero | Op::EqZeroCJump(_) => Op::PushSmall(0),
+            Op::DupCJump(_) => Op::Dup,
+            Op::NotCJump(_) => Op::Not,
+            op => op,
+        }
+    }
+
+    /// Stack values the op reads, over its whole sequence for a
+    /// superinstruction. Ops that only the checked path executes count as 0.
+    pub fn depth(self) -> usize {
+        match self {
+            Op::Add | Op::Sub | Op::Mul | Op::Eq | Op::Gr | Op::Less => 2,
+            Op::Dup | Op::Not | Op::Pop | Op::CJump(_) => 1,
+            Op::AddImm(_) | Op::SubImm(_) | Op::EqZero | Op::EqZeroCJump(_) => 1,
+            Op::DupCJump(_) | Op::NotCJump(_) => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Dense form of a `Program`'s code, built when the program is loaded.
+/// `ops[ip]` corresponds to `program.ops[ip]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Code {
+    pub ops: Vec<Op>,
+    pub consts: Vec<Data>,
+}
+
+impl Code {
+    pub fn lower(program: &Program) -> Self {
+        let mut code = Self {
+            ops: Vec::with_capacity(program.ops.len()),
+            consts: Vec::new(),
+        };
+        for instr in &program.ops {
+            let op = code.lower_instruction(instr);
+            code.ops.push(op);
+        }
+        code.fuse();
+        code
+    }
+
+    /// Replaces the first instruction of every sequence in the fixed list of
+    /// superinstructions with the fused op, preferring the longest match.
+    fn fuse(&mut self) {
+        for ip in 0..self.ops.len() {
+            let next = |n: usize| self.ops.get(ip + n).copied();
+            let fused = match (self.ops[ip], next(1), next(2)) {
+                (Op::PushSmall(0), Some(Op::Eq), Some(Op::CJump(target))) => Op::EqZeroCJump(target),
+                (Op::PushSmall(0), Some(Op::Eq), _) => Op::EqZero,
+                (Op::PushSmall(n), Some(Op::Add), _) => Op::AddImm(n),
+                (Op::PushSmall(n), Some(Op::Sub), _) => Op::SubImm(n),
+                (Op::Dup, Some(Op::CJump(target)), _) => Op::DupCJump(target),
+                (Op::Not, Some(Op::CJump(target)), _) => Op::NotCJump(target),
+                _ => continue,
+            };
+            self.ops[ip] = fused;
+        }
+    }
+
+    fn lower_instruction(&mut self, instr: &Instruction) -> Op {
+        let operand = match instr.operands {
+            Operands::One(data) => Some(data),
+            Operands::Zero => None,
+        };
+        match (instr.op_code, operand) {
+            (OpCode::PushInt, Some(data)) => match data {
+                Data::Int(n) if i32::try_from(n).is_ok() => Op::PushSmall(n as i32),
+                _ => Op::PushConst(self.constant(data)),
+            },
+            (OpCode::PushFloat, Some(data)) => Op::PushFloat(self.constant(data)),
+            (OpCode::Jump, Some(Data::Int(target))) => Op::Jump(address(target)),
+            (OpCode::CJump, Some(Data::Int(target))) => Op::CJump(address(target)),
+            (OpCode::CallNative, Some(Data::Int(id))) => Op::CallNative(address(id)),
+            (OpCode::Call, Some(Data::Int(entry))) => Op::Call(address(entry)),
+            (OpCode::Nop, _) => Op::Nop,
+            (OpCode::Add, _) => Op::Add,
+            (OpCode::Sub, _) => Op::Sub,
+            (OpCode::Mul, _) => Op::Mul,
+            (OpCode::Dup, _) => Op::Dup,
+            (OpCode::Print, _) => Op::Print,
+            (OpCode::Exit, _) => Op::Exit,
+            (OpCode::Panic, _) => Op::Panic,
+            (OpCode::PrintChar, _) => Op::PrintChar,
+            (OpCode::Eq, _) => Op::Eq,
+            (OpCode::Gr, _) => Op::Gr,
+            (OpCode::Less, _) => Op::Less,
+            (OpCode::Not, _) => Op::Not,
+            (OpCode::ReadInt, _) => Op::ReadInt,
+            (OpCode::ReadChar, _) => Op::ReadChar,
+            (OpCode::ReadLine, _) => Op::ReadLine,
+            (OpCode::PrintErr, _) => Op::PrintErr,
+            (OpCode::Ret, _) => Op::Ret,
+            (OpCode::Alloc, _) => Op::Alloc,
+            (OpCode::Load, _) => Op::Load,
+            (OpCode::Store, _) => Op::Store,
+            (OpCode::Pop, _) => Op::Pop,
+            (op_code, _) => Op::Malformed(op_code),
+        }
+    }
+
+    fn constant(&mut self, data: Data) -> u32 {
+        self.consts.push(data);
+        (self.consts.len() - 1) as u32
+    }
+}
+
+/// Addresses and indices outside `u32` can't be valid, so they all map to
+/// `u32::MAX`, which behaves the same: past the end of the code or the links.
+fn address(n: i64) -> u32 {
+    u32::try_from(n).unwrap_or(u32::MAX)
+}
+
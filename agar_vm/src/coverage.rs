@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use agar_core::{Data, OpCode, Operands};
+
+use crate::{Interpreter, StepResult, Tracer};
+
+/// Records which instructions ran and which way each conditional branch went.
+/// Attach it with `Interpreter::add_tracer`, usually behind an `Rc<RefCell<_>>`.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    hits: Vec<u64>,
+    /// Taken and not-taken counts per conditional branch.
+    branches: BTreeMap<usize, (u64, u64)>,
+    pending_branch: Option<usize>,
+}
+
+fn is_branch(op_code: OpCode) -> bool {
+    matches!(op_code, OpCode::CJump)
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of times the instruction at `ip` was executed.
+    pub fn hits(&self, ip: usize) -> u64 {
+        self.hits.get(ip).copied().unwrap_or(0)
+    }
+
+    /// Taken and not-taken counts of the branch at `ip`, if it ever ran.
+    pub fn branch(&self, ip: usize) -> Option<(u64, u64)> {
+        self.branches.get(&ip).copied()
+    }
+
+    /// Writes an LCOV tracefile. With debug info, counts are reported per
+    /// source line of the assembly file; without it every instruction is a
+    /// line of `<program>`, numbered from 1.
+    pub fn write_lcov<W: Write>(&self, vm: &Interpreter, out: &mut W) -> io::Result<()> {
+        let program = &vm.program;
+        let debug = program.debug.as_ref();
+        let line = |ip: usize| {
+            debug
+                .and_then(|debug| debug.location(ip))
+                .map_or(ip + 1, |location| location.line)
+        };
+        let file = debug.map_or("<program>", |debug| debug.file.as_str());
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{file}")?;
+
+        // Functions are the labels used as call targets.
+        let mut functions = BTreeMap::new();
+        for instr in &program.ops {
+            if let (OpCode::Call, Operands::One(Data::Int(entry))) = (instr.op_code, instr.operands)
+            {
+                let entry = entry as usize;
+                if entry < program.ops.len() {
+                    functions.insert(entry, vm.function_name(Some(entry)));
+                }
+            }
+        }
+        for (entry, name) in &functions {
+            writeln!(out, "FN:{},{}", line(*entry), name)?;
+        }
+        for (entry, name) in &functions {
+            writeln!(out, "FNDA:{},{}", self.hits(*entry), name)?;
+        }
+        writeln!(out, "FNF:{}", functions.len())?;
+        let hit = functions
+            .keys()
+            .filter(|entry| self.hits(**entry) > 0)
+            .count();
+        writeln!(out, "FNH:{hit}")?;
+
+        let mut branches = (0, 0);
+        for (ip, instr) in program.ops.iter().enumerate() {
+            if !is_branch(instr.op_code) {
+                continue;
+            }
+            let counts = match self.branch(ip) {
+                Some((taken, not_taken)) => [taken.to_string(), not_taken.to_string()],
+                None => ["-".to_string(), "-".to_string()],
+            };
+            for (i, count) in counts.iter().enumerate() {
+                writeln!(out, "BRDA:{},{},{},{}", line(ip), ip, i, count)?;
+                branches.0 += 1;
+                if count != "-" && count != "0" {
+                    branches.1 += 1;
+                }
+            }
+        }
+        writeln!(out, "BRF:{}", branches.0)?;
+        writeln!(out, "BRH:{}", branches.1)?;
+
+        // A line counts as often as its most executed instruction.
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for ip in 0..program.ops.len() {
+            let count = lines.entry(line(ip)).or_insert(0);
+            *count = (*count).max(self.hits(ip));
+        }
+        for (line, count) in &lines {
+            writeln!(out, "DA:{line},{count}")?;
+        }
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(
+            out,
+            "LH:{}",
+            lines.values().filter(|count| **count > 0).count()
+        )?;
+        writeln!(out, "end_of_record")
+    }
+}
+
+impl Tracer for Coverage {
+    fn before_step(&mut self, vm: &Interpreter) {
+        if self.hits.len() < vm.program.ops.len() {
+            self.hits.resize(vm.program.ops.len(), 0);
+        }
+        self.hits[vm.ip] += 1;
+        let is_branch = vm
+            .program
+            .get(vm.ip)
+            .is_some_and(|instr| is_branch(instr.op_code));
+        self.pending_branch = is_branch.then_some(vm.ip);
+    }
+
+    fn after_step(&mut self, vm: &Interpreter, result: &StepResult) {
+        let ip = match self.pending_branch.take() {
+            Some(ip) => ip,
+            None => return,
+        };
+        if !matches!(result, StepResult::Ok) {
+            return;
+        }
+        // CJump leaves its condition on the stack.
+        let taken = matches!(vm.stack().last(), Some(Data::Int(cond)) if *cond > 0);
+        let counts = self.branches.entry(ip).or_default();
+        if taken {
+            counts.0 += 1;
+        } else {
+            counts.1 += 1;
+        }
+    }
+}
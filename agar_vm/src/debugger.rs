@@ -0,0 +1,342 @@
+use std::io::{self, Write};
+
+use agar_core::OpCode;
+
+use crate::{AgarIo, ExitStatus, Interpreter, StepResult};
+
+const HELP: &str = "\
+step [n]                  execute n instructions, entering calls
+next                      execute one instruction, stepping over calls
+continue                  run until a breakpoint or the end of the program
+reverse-step [n]          undo the last n instructions
+reverse-continue          run backwards to the previous breakpoint or the start
+origin [slot]             go back to the instruction that pushed a stack value
+break <ip|label|:line>    set a breakpoint
+delete [n]                delete breakpoint n, or all of them
+stack                     print the operand stack
+locals                    print the stack slots of the current call frame
+disasm [n]                disassemble n instructions around ip
+set ip <n>                move the instruction pointer
+quit                      stop debugging";
+
+/// Instructions between the snapshots used for reverse execution.
+const HISTORY_INTERVAL: u64 = 256;
+
+/// Line-oriented debugger driving an `Interpreter`.
+pub struct Debugger {
+    pub vm: Interpreter,
+    pub breakpoints: Vec<usize>,
+    /// Assembly source, shown next to the current instruction when debug info is present.
+    pub source: Option<String>,
+    status: Option<ExitStatus>,
+}
+
+impl Debugger {
+    /// Takes over `vm` and enables its execution history for reverse stepping.
+    pub fn new(mut vm: Interpreter) -> Self {
+        vm.enable_history(HISTORY_INTERVAL);
+        Self {
+            vm,
+            breakpoints: Vec::new(),
+            source: None,
+            status: None,
+        }
+    }
+
+    /// Exit status once the program has finished.
+    pub fn status(&self) -> Option<&ExitStatus> {
+        self.status.as_ref()
+    }
+
+    /// Reads commands from `io` until `quit` or the end of input. The program
+    /// reads its own input from `io` too, so both share one input stream.
+    pub fn run<T: AgarIo, W: Write>(&mut self, io: &mut T, out: &mut W) -> io::Result<()> {
+        self.show_position(out)?;
+        write!(out, "(agar) ")?;
+        out.flush()?;
+        while let Some(line) = io.read_line()? {
+            if !self.execute(&line, io, out)? {
+                break;
+            }
+            write!(out, "(agar) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    /// Executes a single command. Returns `false` when the session should end.
+    pub fn execute<T: AgarIo, W: Write>(
+        &mut self,
+        command: &str,
+        io: &mut T,
+        out: &mut W,
+    ) -> io::Result<bool> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["step" | "s"] => self.step(1, io, out)?,
+            ["step" | "s", n] => match n.parse() {
+                Ok(n) => self.step(n, io, out)?,
+                Err(_) => writeln!(out, "Can't read step count")?,
+            },
+            ["next" | "n"] => self.next(io, out)?,
+            ["continue" | "c"] => self.resume(None, io, out)?,
+            ["reverse-step" | "rs"] => self.reverse_step(1, out)?,
+            ["reverse-step" | "rs", n] => match n.parse() {
+                Ok(n) => self.reverse_step(n, out)?,
+                Err(_) => writeln!(out, "Can't read step count")?,
+            },
+            ["reverse-continue" | "rc"] => self.reverse_continue(out)?,
+            ["origin"] => match self.vm.stack().len().checked_sub(1) {
+                Some(slot) => self.origin(slot, out)?,
+                None => writeln!(out, "<empty>")?,
+            },
+            ["origin", slot] => match slot.parse() {
+                Ok(slot) if slot < self.vm.stack().len() => self.origin(slot, out)?,
+                _ => writeln!(out, "No stack slot {slot}")?,
+            },
+            ["break" | "b", location] => match self.resolve(location) {
+                Some(ip) => {
+                    self.breakpoints.push(ip);
+                    writeln!(out, "Breakpoint {} at ip {}", self.breakpoints.len(), ip)?;
+                }
+                None => writeln!(out, "Unknown location {location}")?,
+            },
+            ["break" | "b"] => {
+                for (i, ip) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: ip {}", i + 1, ip)?;
+                }
+            }
+            ["delete" | "d"] => self.breakpoints.clear(),
+            ["delete" | "d", n] => match n.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.breakpoints.len() => {
+                    self.breakpoints.remove(n - 1);
+                }
+                _ => writeln!(out, "No breakpoint {n}")?,
+            },
+            ["stack"] => self.print_slots(0, out)?,
+            ["locals"] => {
+                let base = self.vm.frames.last().map_or(0, |frame| frame.stack_base);
+                self.print_slots(base, out)?;
+            }
+            ["disasm"] => self.disasm(3, out)?,
+            ["disasm", n] => match n.parse() {
+                Ok(n) => self.disasm(n, out)?,
+                Err(_) => writeln!(out, "Can't read instruction count")?,
+            },
+            ["set", "ip", n] => match self.resolve(n) {
+                Some(ip) => {
+                    self.vm.goto(ip);
+                    self.show_position(out)?;
+                }
+                None => writeln!(out, "Unknown location {n}")?,
+            },
+            ["help" | "h"] => writeln!(out, "{HELP}")?,
+            ["quit" | "q"] => return Ok(false),
+            _ => writeln!(out, "Unknown command, try help")?,
+        }
+        Ok(true)
+    }
+
+    /// Turns `12`, `loop` or `:7` into an instruction address.
+    pub fn resolve(&self, location: &str) -> Option<usize> {
+        if let Ok(ip) = location.parse::<usize>() {
+            return Some(ip);
+        }
+        let debug = self.vm.program.debug.as_ref()?;
+        match location.rsplit_once(':') {
+            Some((_, line)) => debug.ip_for_line(line.parse().ok()?),
+            None => debug.label_address(location),
+        }
+    }
+
+    fn finished<W: Write>(&self, out: &mut W) -> io::Result<bool> {
+        if let Some(status) = &self.status {
+            writeln!(out, "Program finished: {status}")?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Executes one instruction, recording the exit status if the program ends.
+    fn step_once<T: AgarIo>(&mut self, io: &mut T) -> bool {
+        let status = match self.vm.step(io) {
+            StepResult::Ok => return true,
+            StepResult::Exit => ExitStatus::Ok,
+            StepResult::Error(e) => ExitStatus::Error(e),
+            StepResult::Panic(e) => {
+                self.vm.panic(io, e);
+                ExitStatus::Panic
+            }
+            StepResult::OutOfFuel => ExitStatus::OutOfFuel,
+        };
+        let _ = io.flush();
+        self.status = Some(status);
+        false
+    }
+
+    fn step<T: AgarIo, W: Write>(&mut self, n: usize, io: &mut T, out: &mut W) -> io::Result<()> {
+        if self.finished(out)? {
+            return Ok(());
+        }
+        for _ in 0..n {
+            if !self.step_once(io) {
+                return self.stopped(out);
+            }
+        }
+        let _ = io.flush();
+        self.show_position(out)
+    }
+
+    fn next<T: AgarIo, W: Write>(&mut self, io: &mut T, out: &mut W) -> io::Result<()> {
+        let is_call = self
+            .vm
+            .program
+            .get(self.vm.ip)
+            .is_some_and(|instr| instr.op_code == OpCode::Call);
+        if is_call {
+            let depth = self.vm.frames.len();
+            self.resume(Some(depth), io, out)
+        } else {
+            self.step(1, io, out)
+        }
+    }
+
+    /// Runs until a breakpoint, the end of the program or, with `depth`, until
+    /// the call stack is back to `depth` frames.
+    fn resume<T: AgarIo, W: Write>(
+        &mut self,
+        depth: Option<usize>,
+        io: &mut T,
+        out: &mut W,
+    ) -> io::Result<()> {
+        if self.finished(out)? {
+            return Ok(());
+        }
+        loop {
+            if !self.step_once(io) {
+                return self.stopped(out);
+            }
+            if depth.is_some_and(|depth| self.vm.frames.len() <= depth) {
+                break;
+            }
+            if let Some(i) = self.breakpoints.iter().position(|ip| *ip == self.vm.ip) {
+                let _ = io.flush();
+                writeln!(out, "Breakpoint {}", i + 1)?;
+                break;
+            }
+        }
+        let _ = io.flush();
+        self.show_position(out)
+    }
+
+    /// Steps back once, forgetting the exit status if the program had finished.
+    fn step_back(&mut self) -> bool {
+        if !self.vm.step_back() {
+            return false;
+        }
+        self.status = None;
+        true
+    }
+
+    fn reverse_step<W: Write>(&mut self, n: usize, out: &mut W) -> io::Result<()> {
+        for _ in 0..n {
+            if !self.step_back() {
+                writeln!(out, "At the start of the recorded history")?;
+                break;
+            }
+        }
+        self.show_position(out)
+    }
+
+    fn reverse_continue<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        loop {
+            if !self.step_back() {
+                writeln!(out, "At the start of the recorded history")?;
+                break;
+            }
+            if let Some(i) = self.breakpoints.iter().position(|ip| *ip == self.vm.ip) {
+                writeln!(out, "Breakpoint {}", i + 1)?;
+                break;
+            }
+        }
+        self.show_position(out)
+    }
+
+    /// Steps back until stack slot `slot` no longer holds its current value,
+    /// stopping at the instruction that put it there.
+    fn origin<W: Write>(&mut self, slot: usize, out: &mut W) -> io::Result<()> {
+        let value = self.vm.stack()[slot];
+        loop {
+            if !self.step_back() {
+                writeln!(out, "At the start of the recorded history")?;
+                break;
+            }
+            if self.vm.stack().get(slot) != Some(&value) {
+                writeln!(out, "[{slot}] {value} was pushed by:")?;
+                break;
+            }
+        }
+        self.show_position(out)
+    }
+
+    fn stopped<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out)?;
+        self.finished(out).map(|_| ())
+    }
+
+    /// Prints the next instruction and, with debug info, the line it came from.
+    pub fn show_position<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let ip = self.vm.ip;
+        let instr = match self.vm.program.get(ip) {
+            Some(instr) => self.vm.program.format_instruction(instr),
+            None => "<end of program>".to_string(),
+        };
+        let location = self
+            .vm
+            .program
+            .debug
+            .as_ref()
+            .and_then(|debug| Some((&debug.file, debug.location(ip)?)));
+        match location {
+            Some((file, location)) => {
+                writeln!(out, "-> {ip}: {instr}  ({}:{})", file, location.line)?;
+                let text = self
+                    .source
+                    .as_ref()
+                    .and_then(|source| source.lines().nth(location.line - 1));
+                if let Some(text) = text {
+                    writeln!(out, "   {} | {}", location.line, text)?;
+                }
+            }
+            None => writeln!(out, "-> {ip}: {instr}")?,
+        }
+        Ok(())
+    }
+
+    fn print_slots<W: Write>(&self, base: usize, out: &mut W) -> io::Result<()> {
+        let stack = self.vm.stack();
+        if stack.len() <= base {
+            return writeln!(out, "<empty>");
+        }
+        for (i, data) in stack.iter().enumerate().skip(base).rev() {
+            writeln!(out, "[{i}] {data} ({})", data.type_name())?;
+        }
+        Ok(())
+    }
+
+    fn disasm<W: Write>(&self, around: usize, out: &mut W) -> io::Result<()> {
+        let program = &self.vm.program;
+        let start = self.vm.ip.saturating_sub(around);
+        let end = (self.vm.ip + around + 1).min(program.ops.len());
+        for (ip, instr) in program.ops.iter().enumerate().take(end).skip(start) {
+            if let Some(label) = program.debug.as_ref().and_then(|d| d.label_at(ip)) {
+                writeln!(out, "   {label}:")?;
+            }
+            let marker = if ip == self.vm.ip { "->" } else { "  " };
+            let mark = if self.breakpoints.contains(&ip) { "*" } else { " " };
+            writeln!(out, "{marker}{mark}{ip:>4}: {}", program.format_instruction(instr))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use agar_core::{Data, OpCode, SourceLocation};
+
+use crate::Frame;
+
+/// Number of call frames printed by `Display` before the rest are summarized.
+const DISPLAYED_FRAMES: usize = 16;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ErrorKind {
+    IncompatibleType,
+    NotEnoughArgs,
+    InvalidValue,
+    StackOverflow,
+    CallDepthExceeded,
+    OutOfMemory,
+    /// A replayed run asked for input the recorded run didn't.
+    ReplayDiverged,
+    Other,
+    /// `readint` at the end of input, where no value can stand for it.
+    EndOfInput,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ErrorKind::IncompatibleType => "incompatible operand types",
+            ErrorKind::NotEnoughArgs => "not enough values on the stack",
+            ErrorKind::InvalidValue => "invalid value",
+            ErrorKind::StackOverflow => "operand stack overflow",
+            ErrorKind::CallDepthExceeded => "maximum call depth exceeded",
+            ErrorKind::OutOfMemory => "out of heap memory",
+            ErrorKind::ReplayDiverged => "replay diverged from the recorded run",
+            ErrorKind::Other => "runtime error",
+            ErrorKind::EndOfInput => "end of input",
+        };
+        write!(f, "{message}")
+    }
+}
+
+/// Error raised while executing an instruction.
+///
+/// The interpreter fills in where it happened, so native functions only need
+/// to provide the kind and, optionally, the offending operands.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub ip: usize,
+    pub op_code: Option<OpCode>,
+    /// Type names of the operands the instruction failed on.
+    pub operands: Vec<&'static str>,
+    /// Active call frames, innermost first.
+    pub backtrace: Vec<Frame>,
+    /// Source file and position of the failing instruction, from debug info.
+    pub file: Option<String>,
+    pub location: Option<SourceLocation>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            ip: 0,
+            op_code: None,
+            operands: Vec::new(),
+            backtrace: Vec::new(),
+            file: None,
+            location: None,
+        }
+    }
+
+    pub fn with_operands(mut self, operands: &[Data]) -> Self {
+        self.operands = operands.iter().map(|data| data.type_name()).collect();
+        self
+    }
+}
+
+impl From<ErrorKind> for RuntimeError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at ip {}", self.kind, self.ip)?;
+        if let (Some(file), Some(location)) = (&self.file, self.location) {
+            write!(f, " ({}:{}:{})", file, location.line, location.column)?;
+        }
+        if let Some(op_code) = self.op_code {
+            write!(f, ": {op_code:?}")?;
+            if !self.operands.is_empty() {
+                write!(f, " on {}", self.operands.join(" and "))?;
+            }
+        }
+        for frame in self.backtrace.iter().take(DISPLAYED_FRAMES) {
+            write!(
+                f,
+                "\n    in function at {} called from ip {}",
+                frame.entry,
+                frame.return_ip.saturating_sub(1)
+            )?;
+        }
+        if self.backtrace.len() > DISPLAYED_FRAMES {
+            write!(f, "\n    ... {} more frames", self.backtrace.len() - DISPLAYED_FRAMES)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
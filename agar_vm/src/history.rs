@@ -0,0 +1,159 @@
+use crate::{Event, Input, Interpreter, MemoryIo};
+
+/// Checkpoints kept before older ones are thinned out.
+const MAX_CHECKPOINTS: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    step: u64,
+    snapshot: Vec<u8>,
+    /// `Interpreter::path` at the checkpoint, which snapshots don't keep.
+    path: u64,
+    /// Inputs received before the checkpoint.
+    inputs: usize,
+}
+
+/// Execution history used to step backwards: periodic snapshots plus every
+/// input received, so any earlier state can be rebuilt by re-executing from
+/// the nearest checkpoint.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    interval: u64,
+    steps: u64,
+    checkpoints: Vec<Checkpoint>,
+    inputs: Vec<Event>,
+    /// Next input to hand out again after stepping back.
+    next_input: usize,
+}
+
+impl History {
+    pub(crate) fn new(interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            steps: 0,
+            checkpoints: Vec::new(),
+            inputs: Vec::new(),
+            next_input: 0,
+        }
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.steps += 1;
+    }
+
+    /// Input the instruction at `ip` received the first time it ran, if this
+    /// part of the execution is being replayed.
+    pub(crate) fn replay(&mut self, ip: usize) -> Option<Input> {
+        let event = self.inputs.get(self.next_input)?;
+        if event.ip != ip {
+            // Execution took a different path, e.g. after `goto`.
+            self.inputs.truncate(self.next_input);
+            return None;
+        }
+        self.next_input += 1;
+        Some(event.input.clone())
+    }
+
+    pub(crate) fn record(&mut self, ip: usize, path: u64, input: &Input) {
+        self.inputs.truncate(self.next_input);
+        self.inputs.push(Event {
+            ip,
+            path,
+            input: input.clone(),
+        });
+        self.next_input = self.inputs.len();
+    }
+}
+
+impl Interpreter {
+    /// Starts keeping history for `step_back`, with a snapshot every
+    /// `interval` instructions. Inputs are recorded so that re-execution is
+    /// deterministic.
+    pub fn enable_history(&mut self, interval: u64) {
+        self.history = Some(History::new(interval));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Number of instructions executed since history was enabled.
+    pub fn steps_executed(&self) -> Option<u64> {
+        self.history.as_ref().map(|history| history.steps)
+    }
+
+    /// Takes a checkpoint if one is due. Called before every instruction.
+    pub(crate) fn checkpoint(&mut self) {
+        let history = match &self.history {
+            Some(history) => history,
+            None => return,
+        };
+        let due = history.steps % history.interval == 0
+            && history.checkpoints.last().is_none_or(|c| c.step < history.steps);
+        if !due {
+            return;
+        }
+        let snapshot = self.snapshot();
+        let history = self.history.as_mut().unwrap();
+        history.checkpoints.push(Checkpoint {
+            step: history.steps,
+            snapshot,
+            path: self.path,
+            inputs: history.next_input,
+        });
+        if history.checkpoints.len() > MAX_CHECKPOINTS {
+            // Keep every other checkpoint and take them half as often.
+            let mut i = 0;
+            history.checkpoints.retain(|_| {
+                i += 1;
+                i % 2 == 1
+            });
+            history.interval *= 2;
+        }
+    }
+
+    /// Undoes the last executed instruction. Returns `false` without history
+    /// or when already at the first recorded state.
+    pub fn step_back(&mut self) -> bool {
+        let target = match &self.history {
+            Some(history) if history.steps > 0 => history.steps - 1,
+            _ => return false,
+        };
+        self.rewind(target)
+    }
+
+    /// Rebuilds the state after `target` executed instructions.
+    fn rewind(&mut self, target: u64) -> bool {
+        let mut history = match self.history.take() {
+            Some(history) => history,
+            None => return false,
+        };
+        let checkpoint = match history.checkpoints.iter().rev().find(|c| c.step <= target) {
+            Some(checkpoint) => checkpoint.clone(),
+            None => {
+                self.history = Some(history);
+                return false;
+            }
+        };
+        if self.restore(&checkpoint.snapshot).is_err() {
+            self.history = Some(history);
+            return false;
+        }
+        history.checkpoints.retain(|c| c.step <= checkpoint.step);
+        history.steps = checkpoint.step;
+        history.next_input = checkpoint.inputs;
+        self.history = Some(history);
+        self.path = checkpoint.path;
+
+        // Re-execute without tracers or visible output.
+        let tracers = self.take_tracers();
+        let mut io = MemoryIo::new();
+        while self.steps_executed().is_some_and(|steps| steps < target) {
+            self.step(&mut io);
+        }
+        for tracer in tracers {
+            self.add_tracer(tracer);
+        }
+        true
+    }
+}
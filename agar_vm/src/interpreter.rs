@@ -1,28 +1,142 @@
-use std::io::Write;
+use std::error::Error;
+use std::fmt::Display;
 
-use agar_core::{Data, OpCode, Operands, Program};
+use agar_core::{verify, Data, Int, OpCode, Program, VerifiedProgram};
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+use crate::code::{Code, Op};
+use crate::history::History;
+use crate::replay::{extend_path, ReplayMode, PATH_START};
+use crate::trace::Tracers;
+use crate::{
+    AgarIo, ErrorKind, Event, EventLog, Input, InterruptHandle, LoadError, Native, NativeCtx,
+    NativeFn, ReplayError, RuntimeError, Tracer,
+};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum StepResult {
     Ok,
     Error(RuntimeError),
     Panic(&'static str),
     Exit,
+    OutOfFuel,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub enum RuntimeError {
-    IncompatibleType,
-    NotEnoughArgs,
-    InvalidValue,
-    Other,
-}
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ExitStatus {
     Ok,
     Error(RuntimeError),
     Panic,
+    /// Not enough fuel was left for the next instruction. Execution can be
+    /// resumed after adding fuel.
+    OutOfFuel,
+    /// Stopped through an `InterruptHandle`. Execution can be resumed.
+    Interrupted,
+}
+
+impl ExitStatus {
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        match self {
+            ExitStatus::Error(e) => Some(e.kind),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitStatus::Ok => write!(f, "ok"),
+            ExitStatus::Error(e) => write!(f, "runtime error: {e}"),
+            ExitStatus::Panic => write!(f, "panic"),
+            ExitStatus::OutOfFuel => write!(f, "out of fuel"),
+            ExitStatus::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl Error for ExitStatus {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExitStatus::Error(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Number of steps between checks of the interrupt flag.
+const INTERRUPT_CHECK_INTERVAL: usize = 1024;
+
+/// Resource limits and checks enforced while loading and running a program.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InterpreterConfig {
+    /// Maximum number of values on the operand stack.
+    pub max_stack: usize,
+    /// Maximum number of nested `Call`s.
+    pub max_call_depth: usize,
+    /// Maximum number of heap cells.
+    pub max_heap: usize,
+    /// Refuse programs that don't pass `agar_core::verify`.
+    pub verify: bool,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        Self {
+            max_stack: 1 << 20,
+            max_call_depth: 10_000,
+            // Data is 24 bytes, so the heap tops out around 24 MB.
+            max_heap: 1 << 20,
+            verify: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Frame {
+    /// Address of the called function.
+    pub entry: usize,
+    pub return_ip: usize,
+    /// Stack length when the function was called.
+    pub stack_base: usize,
+}
+
+/// Fuel charged for executing each opcode.
+#[derive(Debug, Clone)]
+pub struct CostTable {
+    costs: [u64; 256],
+}
+
+impl CostTable {
+    /// Every opcode costs `cost`.
+    pub fn uniform(cost: u64) -> Self {
+        Self { costs: [cost; 256] }
+    }
+
+    pub fn cost(&self, op_code: OpCode) -> u64 {
+        self.costs[op_code.as_byte() as usize]
+    }
+
+    pub fn set(&mut self, op_code: OpCode, cost: u64) {
+        self.costs[op_code.as_byte() as usize] = cost;
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        let mut table = Self::uniform(1);
+        for op_code in [
+            OpCode::Print,
+            OpCode::PrintChar,
+            OpCode::PrintErr,
+            OpCode::ReadInt,
+            OpCode::ReadChar,
+            OpCode::ReadLine,
+        ] {
+            table.set(op_code, 5);
+        }
+        table.set(OpCode::CallNative, 10);
+        table
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -30,128 +144,435 @@ pub struct Interpreter {
     pub stack: Vec<Data>,
     pub program: Program,
     pub ip: usize,
+    pub frames: Vec<Frame>,
+    pub heap: Vec<Data>,
+    config: InterpreterConfig,
+    natives: Vec<Native>,
+    links: Vec<usize>,
+    fuel: Option<u64>,
+    cost_table: CostTable,
+    interrupt: InterruptHandle,
+    tracers: Tracers,
+    replay: ReplayMode,
+    /// `Event::path` of the next input, kept while recording or replaying.
+    pub(crate) path: u64,
+    pub(crate) history: Option<History>,
+    /// `program` lowered for dispatch.
+    code: Code,
+    /// The loaded program was verified free of stack underflow.
+    unchecked: bool,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_config(InterpreterConfig::default())
+    }
+
+    pub fn with_config(config: InterpreterConfig) -> Self {
         Self {
             stack: Vec::new(),
             program: Program::new(),
             ip: 0,
+            frames: Vec::new(),
+            heap: Vec::new(),
+            config,
+            natives: Vec::new(),
+            links: Vec::new(),
+            fuel: None,
+            cost_table: CostTable::default(),
+            interrupt: InterruptHandle::new(),
+            tracers: Tracers::default(),
+            replay: ReplayMode::Off,
+            path: PATH_START,
+            history: None,
+            code: Code::default(),
+            unchecked: false,
+        }
+    }
+
+    /// Returns a handle that can stop `run` from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn config(&self) -> &InterpreterConfig {
+        &self.config
+    }
+
+    /// Remaining fuel, or `None` when execution is unmetered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Sets remaining fuel. `None` disables metering.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+
+    pub fn set_cost_table(&mut self, cost_table: CostTable) {
+        self.cost_table = cost_table;
+    }
+
+    /// Makes `func` available to programs importing `name`. Registering the same
+    /// name again replaces the previous function. Natives must be registered
+    /// before the program that imports them is loaded.
+    pub fn register_native(&mut self, name: &str, arity: usize, func: NativeFn) {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            func,
+        };
+        match self.natives.iter_mut().find(|n| n.name == name) {
+            Some(slot) => *slot = native,
+            None => self.natives.push(native),
+        }
+    }
+
+    /// Loads `program` after resolving its import table against registered natives.
+    /// With `InterpreterConfig::verify`, the program is verified first.
+    pub fn load_program(&mut self, program: Program) -> Result<(), LoadError> {
+        if self.config.verify {
+            let verified = verify(&program).map_err(LoadError::Unverified)?;
+            return self.load_verified(verified);
         }
+        self.link(program)
+    }
+
+    /// Loads a program that already passed verification.
+    /// `run` uses a faster dispatch loop for it when possible.
+    pub fn load_verified(&mut self, program: VerifiedProgram) -> Result<(), LoadError> {
+        let unchecked = program.underflow_free();
+        self.link(program.into_program())?;
+        self.unchecked = unchecked;
+        Ok(())
     }
 
-    pub fn load_program(&mut self, program: Program) {
+    fn link(&mut self, program: Program) -> Result<(), LoadError> {
+        self.unchecked = false;
+        let mut links = Vec::with_capacity(program.imports.len());
+        for import in &program.imports {
+            let index = match self.natives.iter().position(|n| n.name == import.name) {
+                Some(index) => index,
+                None => return Err(LoadError::MissingImport(import.name.clone())),
+            };
+            if self.natives[index].arity != import.arity {
+                return Err(LoadError::ArityMismatch {
+                    name: import.name.clone(),
+                    expected: import.arity,
+                    found: self.natives[index].arity,
+                });
+            }
+            links.push(index);
+        }
+        self.links = links;
+        self.code = Code::lower(&program);
         self.program = program;
+        Ok(())
     }
 
-    pub fn step<T: Write>(&mut self, output: &mut T) -> StepResult {
-        match self.program.get(self.ip) {
-            Some(instr) => {
-                match instr.op_code {
-                    OpCode::PushInt => {
-                        if let Operands::One(data) = instr.operands {
-                            self.stack.push(data);
-                        }
+    /// The loaded program in the form the interpreter executes.
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+
+    /// Name of the function starting at `entry`: its label, or `main` at top level.
+    pub fn function_name(&self, entry: Option<usize>) -> String {
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return "main".to_string(),
+        };
+        let debug = self.program.debug.as_ref();
+        match debug.and_then(|debug| debug.label_at(entry)) {
+            Some(label) => label.to_string(),
+            None => format!("function@{entry}"),
+        }
+    }
+
+    /// Starts recording every input the program receives.
+    pub fn start_recording(&mut self) {
+        self.replay = ReplayMode::Record(EventLog {
+            program: self.program.hash(),
+            events: Vec::new(),
+        });
+        self.path = PATH_START;
+    }
+
+    /// Stops recording and returns the inputs recorded so far.
+    pub fn take_recording(&mut self) -> Option<EventLog> {
+        match std::mem::take(&mut self.replay) {
+            ReplayMode::Record(log) => Some(log),
+            other => {
+                self.replay = other;
+                None
+            }
+        }
+    }
+
+    /// Feeds the inputs from `log` to the program instead of reading them.
+    /// Asking for input at another instruction, or after executing other
+    /// instructions than the recorded run did since the previous input, fails
+    /// with `ErrorKind::ReplayDiverged`.
+    pub fn start_replay(&mut self, log: EventLog) -> Result<(), ReplayError> {
+        let expected = self.program.hash();
+        if log.program != expected {
+            return Err(ReplayError::ProgramMismatch {
+                expected,
+                found: log.program,
+            });
+        }
+        self.replay = ReplayMode::Replay(log, 0);
+        self.path = PATH_START;
+        Ok(())
+    }
+
+    /// Number of recorded inputs not consumed yet, when replaying. Anything
+    /// left after the program ends means the replay diverged.
+    pub fn replay_remaining(&self) -> Option<usize> {
+        match &self.replay {
+            ReplayMode::Replay(log, next) => Some(log.events.len() - next),
+            _ => None,
+        }
+    }
+
+    /// Replaces all attached tracers with `tracer`.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracers.0 = vec![tracer];
+    }
+
+    /// Attaches another tracer. Tracers are called in the order they were added.
+    pub fn add_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracers.0.push(tracer);
+    }
+
+    /// Detaches and returns all tracers.
+    pub fn take_tracers(&mut self) -> Vec<Box<dyn Tracer>> {
+        std::mem::take(&mut self.tracers.0)
+    }
+
+    pub fn step<T: AgarIo>(&mut self, io: &mut T) -> StepResult {
+        if self.program.get(self.ip).is_none() {
+            return self.execute(io);
+        }
+        if self.history.is_none() {
+            return self.traced_step(io);
+        }
+        self.checkpoint();
+        let result = self.traced_step(io);
+        if let Some(history) = &mut self.history {
+            history.advance();
+        }
+        result
+    }
+
+    fn traced_step<T: AgarIo>(&mut self, io: &mut T) -> StepResult {
+        if self.tracers.0.is_empty() {
+            return self.execute(io);
+        }
+        let mut tracers = std::mem::take(&mut self.tracers.0);
+        for tracer in tracers.iter_mut() {
+            tracer.before_step(self);
+        }
+        let result = self.execute(io);
+        for tracer in tracers.iter_mut() {
+            tracer.after_step(self, &result);
+        }
+        tracers.append(&mut self.tracers.0);
+        self.tracers.0 = tracers;
+        result
+    }
+
+    fn execute<T: AgarIo>(&mut self, io: &mut T) -> StepResult {
+        match self.code.ops.get(self.ip).map(|op| op.unfused()) {
+            Some(op) => {
+                if let Some(fuel) = self.fuel {
+                    let cost = self.cost_table.cost(op.op_code());
+                    if fuel < cost {
+                        return StepResult::OutOfFuel;
                     }
-                    OpCode::PushFloat => {
-                        if let Operands::One(data) = instr.operands {
-                            self.stack.push(data);
+                    self.fuel = Some(fuel - cost);
+                }
+                if !matches!(self.replay, ReplayMode::Off) {
+                    self.path = extend_path(self.path, self.ip);
+                }
+                if self.stack.len() + self.growth(op) > self.config.max_stack {
+                    return self.error(ErrorKind::StackOverflow, &[]);
+                }
+                match op {
+                    Op::PushSmall(n) => self.stack.push(Data::Int(n as Int)),
+                    Op::PushConst(index) | Op::PushFloat(index) => {
+                        self.stack.push(self.code.consts[index as usize]);
+                    }
+                    Op::Print => {
+                        let data = if let Some(a) = self.stack.pop() {
+                            a
+                        } else {
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
+                        };
+                        if io.write_out(format_args!("{}", data)).is_err() {
+                            return self.error(ErrorKind::Other, &[]);
                         }
                     }
-                    OpCode::Print => {
+                    Op::PrintErr => {
                         let data = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
-                        if write!(output, "{}", data).is_err() {
-                            return StepResult::Error(RuntimeError::Other);
+                        if io.write_err(format_args!("{}", data)).is_err() {
+                            return self.error(ErrorKind::Other, &[]);
                         }
                     }
-                    OpCode::PrintChar => {
+                    Op::PrintChar => {
                         let data = if let Some(a) = self.stack.pop() {
                             if let Data::Int(b) = a {
                                 b
                             } else {
-                                return StepResult::Error(RuntimeError::IncompatibleType);
+                                return self.error(ErrorKind::IncompatibleType, &[a]);
                             }
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let ch = if let Some(a) = char::from_u32(data as u32) {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::InvalidValue);
+                            return self.error(ErrorKind::InvalidValue, &[]);
                         };
-                        if write!(output, "{}", ch).is_err() {
-                            return StepResult::Error(RuntimeError::Other);
+                        if io.write_out(format_args!("{}", ch)).is_err() {
+                            return self.error(ErrorKind::Other, &[]);
+                        }
+                    }
+                    Op::ReadInt => {
+                        let input = self.input(|vm| {
+                            if io.flush().is_err() {
+                                return Err(vm.error(ErrorKind::Other, &[]));
+                            }
+                            match io.read_int() {
+                                Ok(n) => Ok(Input::Int(n)),
+                                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                                    Ok(Input::InvalidInt)
+                                }
+                                Err(_) => Err(vm.error(ErrorKind::Other, &[])),
+                            }
+                        });
+                        match input {
+                            Ok(Input::Int(Some(n))) => self.stack.push(Data::Int(n)),
+                            Ok(Input::Int(None)) => return self.error(ErrorKind::EndOfInput, &[]),
+                            Ok(Input::InvalidInt) => return self.error(ErrorKind::InvalidValue, &[]),
+                            Ok(_) => return self.error(ErrorKind::ReplayDiverged, &[]),
+                            Err(e) => return e,
+                        }
+                    }
+                    Op::ReadChar => {
+                        let input = self.input(|vm| {
+                            if io.flush().is_err() {
+                                return Err(vm.error(ErrorKind::Other, &[]));
+                            }
+                            match io.read_char() {
+                                Ok(ch) => Ok(Input::Char(ch)),
+                                Err(_) => Err(vm.error(ErrorKind::Other, &[])),
+                            }
+                        });
+                        match input {
+                            Ok(Input::Char(Some(ch))) => self.stack.push(Data::Int(ch as i64)),
+                            Ok(Input::Char(None)) => self.stack.push(Data::Int(-1)),
+                            Ok(_) => return self.error(ErrorKind::ReplayDiverged, &[]),
+                            Err(e) => return e,
                         }
                     }
-                    OpCode::Add => {
+                    Op::ReadLine => {
+                        let input = self.input(|vm| {
+                            if io.flush().is_err() {
+                                return Err(vm.error(ErrorKind::Other, &[]));
+                            }
+                            match io.read_line() {
+                                Ok(line) => Ok(Input::Line(line)),
+                                Err(_) => Err(vm.error(ErrorKind::Other, &[])),
+                            }
+                        });
+                        match input {
+                            Ok(Input::Line(Some(line))) => {
+                                let chars: Vec<char> = line.chars().collect();
+                                if self.stack.len() + chars.len() + 1 > self.config.max_stack {
+                                    return self.error(ErrorKind::StackOverflow, &[]);
+                                }
+                                for ch in chars.iter().rev() {
+                                    self.stack.push(Data::Int(*ch as i64));
+                                }
+                                self.stack.push(Data::Int(chars.len() as i64));
+                            }
+                            Ok(Input::Line(None)) => self.stack.push(Data::Int(-1)),
+                            Ok(_) => return self.error(ErrorKind::ReplayDiverged, &[]),
+                            Err(e) => return e,
+                        }
+                    }
+                    Op::Add => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if let Some(x) = a + b {
                             self.stack.push(x);
                         } else {
-                            return StepResult::Error(RuntimeError::IncompatibleType);
+                            return self.error(ErrorKind::IncompatibleType, &[b, a]);
                         }
                     }
-                    OpCode::Sub => {
+                    Op::Sub => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if let Some(x) = b - a {
                             self.stack.push(x);
                         } else {
-                            return StepResult::Error(RuntimeError::IncompatibleType);
+                            return self.error(ErrorKind::IncompatibleType, &[b, a]);
                         }
                     }
-                    OpCode::Mul => {
+                    Op::Mul => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if let Some(x) = a * b {
                             self.stack.push(x);
                         } else {
-                            return StepResult::Error(RuntimeError::IncompatibleType);
+                            return self.error(ErrorKind::IncompatibleType, &[b, a]);
                         }
                     }
-                    OpCode::Eq => {
+                    Op::Eq => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if a == b {
@@ -160,16 +581,16 @@ impl Interpreter {
                             self.stack.push(Data::Int(0));
                         }
                     }
-                    OpCode::Gr => {
+                    Op::Gr => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if b > a {
@@ -178,16 +599,16 @@ impl Interpreter {
                             self.stack.push(Data::Int(0));
                         }
                     }
-                    OpCode::Less => {
+                    Op::Less => {
                         let a = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         let b = if let Some(a) = self.stack.pop() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
 
                         if b < a {
@@ -196,11 +617,11 @@ impl Interpreter {
                             self.stack.push(Data::Int(0));
                         }
                     }
-                    OpCode::Not => {
+                    Op::Not => {
                         let a = if let Some(a) = self.stack.last() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         match a {
                             Data::Int(a) => {
@@ -219,45 +640,139 @@ impl Interpreter {
                             }
                         }
                     }
-                    OpCode::Dup => {
+                    Op::Dup => {
                         let a = if let Some(a) = self.stack.last() {
                             a
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                         self.stack.push(*a);
                     }
-                    OpCode::Jump => {
-                        if let Operands::One(Data::Int(new_ip)) = instr.operands {
-                            self.ip = new_ip as usize;
-                            return StepResult::Ok;
-                        } else {
-                            return StepResult::Error(RuntimeError::InvalidValue);
-                        }
+                    Op::Jump(new_ip) => {
+                        self.ip = new_ip as usize;
+                        return StepResult::Ok;
                     }
-                    OpCode::CJump => {
+                    Op::CJump(new_ip) => {
                         if let Some(a) = self.stack.last() {
                             if let Data::Int(cond) = a {
                                 if *cond > 0 {
-                                    if let Operands::One(Data::Int(new_ip)) = instr.operands {
-                                        self.ip = new_ip as usize;
-                                        return StepResult::Ok;
-                                    } else {
-                                        return StepResult::Error(RuntimeError::InvalidValue);
-                                    }
+                                    self.ip = new_ip as usize;
+                                    return StepResult::Ok;
                                 }
                             } else {
-                                return StepResult::Error(RuntimeError::InvalidValue);
+                                return self.error(ErrorKind::InvalidValue, &[*a]);
                             }
                         } else {
-                            return StepResult::Error(RuntimeError::NotEnoughArgs);
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
                         };
                     }
-                    OpCode::Panic => {
+                    Op::CallNative(id) => {
+                        let native = match self.links.get(id as usize) {
+                            Some(index) => &self.natives[*index],
+                            None => return self.error(ErrorKind::InvalidValue, &[]),
+                        };
+                        let (func, arity) = (native.func, native.arity);
+                        if self.stack.len() < arity {
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
+                        }
+                        let args = self.stack.split_off(self.stack.len() - arity);
+                        // A native error keeps its details in the live run; a replayed
+                        // one only has the kind.
+                        let mut error = None;
+                        let input = self.input(|vm| {
+                            let result = func(&mut NativeCtx { ip: vm.ip, io }, &args);
+                            Ok(Input::Native(result.map_err(|e| {
+                                let kind = e.kind;
+                                error = Some(e);
+                                kind
+                            })))
+                        });
+                        match input {
+                            Ok(Input::Native(Ok(result))) => self.stack.push(result),
+                            Ok(Input::Native(Err(kind))) => {
+                                return self.fail(error.unwrap_or_else(|| RuntimeError::new(kind)))
+                            }
+                            Ok(_) => return self.error(ErrorKind::ReplayDiverged, &[]),
+                            Err(e) => return e,
+                        }
+                    }
+                    Op::Call(entry) => {
+                        if self.frames.len() >= self.config.max_call_depth {
+                            return self.error(ErrorKind::CallDepthExceeded, &[]);
+                        }
+                        self.frames.push(Frame {
+                            entry: entry as usize,
+                            return_ip: self.ip + 1,
+                            stack_base: self.stack.len(),
+                        });
+                        self.ip = entry as usize;
+                        return StepResult::Ok;
+                    }
+                    Op::Ret => {
+                        if let Some(frame) = self.frames.pop() {
+                            self.ip = frame.return_ip;
+                            return StepResult::Ok;
+                        } else {
+                            return self.error(ErrorKind::InvalidValue, &[]);
+                        }
+                    }
+                    Op::Alloc => {
+                        let size = match self.stack.pop() {
+                            Some(Data::Int(size)) if size >= 0 => size as usize,
+                            Some(Data::Int(_)) => return self.error(ErrorKind::InvalidValue, &[]),
+                            Some(other) => return self.error(ErrorKind::IncompatibleType, &[other]),
+                            None => return self.error(ErrorKind::NotEnoughArgs, &[]),
+                        };
+                        if self.heap.len().saturating_add(size) > self.config.max_heap {
+                            return self.error(ErrorKind::OutOfMemory, &[]);
+                        }
+                        self.stack.push(Data::Int(self.heap.len() as i64));
+                        self.heap.resize(self.heap.len() + size, Data::Int(0));
+                    }
+                    Op::Load => {
+                        let addr = match self.stack.pop() {
+                            Some(Data::Int(addr)) => addr,
+                            Some(other) => return self.error(ErrorKind::IncompatibleType, &[other]),
+                            None => return self.error(ErrorKind::NotEnoughArgs, &[]),
+                        };
+                        match usize::try_from(addr).ok().and_then(|a| self.heap.get(a)) {
+                            Some(data) => self.stack.push(*data),
+                            None => return self.error(ErrorKind::InvalidValue, &[]),
+                        }
+                    }
+                    Op::Store => {
+                        let data = if let Some(a) = self.stack.pop() {
+                            a
+                        } else {
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
+                        };
+                        let addr = match self.stack.pop() {
+                            Some(Data::Int(addr)) => addr,
+                            Some(other) => return self.error(ErrorKind::IncompatibleType, &[other]),
+                            None => return self.error(ErrorKind::NotEnoughArgs, &[]),
+                        };
+                        match usize::try_from(addr).ok().and_then(|a| self.heap.get_mut(a)) {
+                            Some(cell) => *cell = data,
+                            None => return self.error(ErrorKind::InvalidValue, &[]),
+                        }
+                    }
+                    Op::Pop => {
+                        if self.stack.pop().is_none() {
+                            return self.error(ErrorKind::NotEnoughArgs, &[]);
+                        }
+                    }
+                    Op::Panic => {
                         return StepResult::Panic("Panic from code");
                     }
-                    OpCode::Exit => return StepResult::Exit,
-                    OpCode::Nop => {}
+                    Op::Exit => return StepResult::Exit,
+                    Op::Nop => {}
+                    Op::Malformed(_) => return self.error(ErrorKind::InvalidValue, &[]),
+                    Op::AddImm(_)
+                    | Op::SubImm(_)
+                    | Op::EqZero
+                    | Op::EqZeroCJump(_)
+                    | Op::DupCJump(_)
+                    | Op::NotCJump(_) => unreachable!("superinstructions are unfused by now"),
                 }
                 self.ip += 1;
                 StepResult::Ok
@@ -266,22 +781,290 @@ impl Interpreter {
         }
     }
 
-    pub fn run<T: Write>(&mut self, output: &mut T) -> ExitStatus {
-        loop {
-            let a = self.step(output);
+    /// Values `op` can add to the stack, which must fit under `max_stack`
+    /// before it runs. `readline` checks for the rest of its line once read.
+    fn growth(&self, op: Op) -> usize {
+        match op {
+            Op::PushSmall(_) | Op::PushConst(_) | Op::PushFloat(_) | Op::Dup | Op::Not => 1,
+            Op::ReadInt | Op::ReadChar | Op::ReadLine => 1,
+            Op::CallNative(id) => match self.links.get(id as usize) {
+                Some(index) if self.natives[*index].arity == 0 => 1,
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    /// Runs until the program ends. Programs loaded with `load_verified` run
+    /// in an unchecked dispatch loop unless fuel, tracers, history or
+    /// recording and replaying are in use.
+    pub fn run<T: AgarIo>(&mut self, io: &mut T) -> ExitStatus {
+        if self.unchecked
+            && self.fuel.is_none()
+            && self.tracers.0.is_empty()
+            && self.history.is_none()
+            && matches!(self.replay, ReplayMode::Off)
+        {
+            return self.run_unchecked(io);
+        }
+        let mut steps = 0;
+        let status = loop {
+            if steps % INTERRUPT_CHECK_INTERVAL == 0 && self.interrupt.take() {
+                break ExitStatus::Interrupted;
+            }
+            steps += 1;
+            let a = self.step(io);
             match a {
-                StepResult::Error(e) => return ExitStatus::Error(e),
+                StepResult::Error(e) => break ExitStatus::Error(e),
                 StepResult::Panic(e) => {
-                    self.panic(output, e);
-                    return ExitStatus::Panic;
+                    self.panic(io, e);
+                    break ExitStatus::Panic;
                 }
                 StepResult::Ok => {}
-                StepResult::Exit => return ExitStatus::Ok,
+                StepResult::Exit => break ExitStatus::Ok,
+                StepResult::OutOfFuel => break ExitStatus::OutOfFuel,
+            }
+        };
+        let _ = io.flush();
+        status
+    }
+
+    /// Dispatch loop for verified programs. Stack underflow was ruled out by
+    /// the verifier, so instead of checking every pop like `execute`, each
+    /// instruction compares the stack length with `Op::depth` once. State set
+    /// from outside, like a modified `stack`, can still be short; such an
+    /// instruction goes through `execute`, which reports the underflow.
+    fn run_unchecked<T: AgarIo>(&mut self, io: &mut T) -> ExitStatus {
+        let mut steps = 0;
+        let status = loop {
+            if steps % INTERRUPT_CHECK_INTERVAL == 0 && self.interrupt.take() {
+                break ExitStatus::Interrupted;
+            }
+            steps += 1;
+            let op = match self.code.ops.get(self.ip) {
+                Some(op) => *op,
+                None => break ExitStatus::Ok,
+            };
+            // Superinstructions push before they pop. Near the limit, run them
+            // one instruction at a time so an overflow is reported where it happens.
+            let op = if self.stack.len() >= self.config.max_stack {
+                op.unfused()
+            } else {
+                op
+            };
+            if self.stack.len() + self.growth(op) > self.config.max_stack {
+                break self.stopped(self.error(ErrorKind::StackOverflow, &[]), io);
+            }
+            if self.stack.len() < op.depth() {
+                match self.execute(io) {
+                    StepResult::Ok => continue,
+                    other => break self.stopped(other, io),
+                }
+            }
+            let stack = &mut self.stack;
+            match op {
+                Op::PushSmall(n) => stack.push(Data::Int(n as Int)),
+                Op::PushConst(index) | Op::PushFloat(index) => {
+                    stack.push(self.code.consts[index as usize]);
+                }
+                Op::Add | Op::Sub | Op::Mul => {
+                    let [.., b, a] = stack[..] else {
+                        unreachable!()
+                    };
+                    stack.truncate(stack.len() - 2);
+                    let result = match op {
+                        Op::Add => a + b,
+                        Op::Sub => b - a,
+                        _ => a * b,
+                    };
+                    match result {
+                        Some(x) => stack.push(x),
+                        None => break self.stopped(self.error(ErrorKind::IncompatibleType, &[b, a]), io),
+                    }
+                }
+                Op::Eq | Op::Gr | Op::Less => {
+                    let [.., b, a] = stack[..] else {
+                        unreachable!()
+                    };
+                    stack.truncate(stack.len() - 2);
+                    let result = match op {
+                        Op::Eq => a == b,
+                        Op::Gr => b > a,
+                        _ => b < a,
+                    };
+                    stack.push(Data::Int(result as i64));
+                }
+                Op::Dup => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    stack.push(a);
+                }
+                Op::Not => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    let zero = match a {
+                        Data::Int(a) => a == 0,
+                        Data::Float(a) => a.is_zero(),
+                    };
+                    stack.push(Data::Int(zero as i64));
+                }
+                Op::Pop => {
+                    stack.truncate(stack.len() - 1);
+                }
+                Op::Jump(target) => {
+                    self.ip = target as usize;
+                    continue;
+                }
+                Op::CJump(target) => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    match a {
+                        Data::Int(cond) if cond > 0 => {
+                            self.ip = target as usize;
+                            continue;
+                        }
+                        Data::Int(_) => {}
+                        a => {
+                            break self.stopped(self.error(ErrorKind::InvalidValue, &[a]), io);
+                        }
+                    }
+                }
+                Op::AddImm(n) | Op::SubImm(n) => {
+                    let a = Data::Int(n as Int);
+                    let [.., b] = stack[..] else { unreachable!() };
+                    stack.truncate(stack.len() - 1);
+                    let result = match op {
+                        Op::AddImm(_) => a + b,
+                        _ => b - a,
+                    };
+                    match result {
+                        Some(x) => stack.push(x),
+                        None => {
+                            self.ip += 1;
+                            break self.stopped(self.error(ErrorKind::IncompatibleType, &[b, a]), io);
+                        }
+                    }
+                    self.ip += 2;
+                    continue;
+                }
+                Op::EqZero | Op::EqZeroCJump(_) => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    let zero = a == Data::Int(0);
+                    stack.truncate(stack.len() - 1);
+                    stack.push(Data::Int(zero as i64));
+                    self.ip = match op {
+                        Op::EqZeroCJump(target) if zero => target as usize,
+                        Op::EqZeroCJump(_) => self.ip + 3,
+                        _ => self.ip + 2,
+                    };
+                    continue;
+                }
+                Op::DupCJump(target) => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    stack.push(a);
+                    match a {
+                        Data::Int(cond) if cond > 0 => self.ip = target as usize,
+                        Data::Int(_) => self.ip += 2,
+                        a => {
+                            self.ip += 1;
+                            break self.stopped(self.error(ErrorKind::InvalidValue, &[a]), io);
+                        }
+                    }
+                    continue;
+                }
+                Op::NotCJump(target) => {
+                    let [.., a] = stack[..] else { unreachable!() };
+                    let zero = match a {
+                        Data::Int(a) => a == 0,
+                        Data::Float(a) => a.is_zero(),
+                    };
+                    stack.push(Data::Int(zero as i64));
+                    self.ip = if zero { target as usize } else { self.ip + 2 };
+                    continue;
+                }
+                _ => match self.execute(io) {
+                    StepResult::Ok => continue,
+                    other => break self.stopped(other, io),
+                },
+            }
+            self.ip += 1;
+        };
+        let _ = io.flush();
+        status
+    }
+
+    /// Exit status for a step that ended the program.
+    fn stopped<T: AgarIo>(&mut self, result: StepResult, io: &mut T) -> ExitStatus {
+        match result {
+            StepResult::Ok | StepResult::Exit => ExitStatus::Ok,
+            StepResult::Error(e) => ExitStatus::Error(e),
+            StepResult::Panic(e) => {
+                self.panic(io, e);
+                ExitStatus::Panic
             }
+            StepResult::OutOfFuel => ExitStatus::OutOfFuel,
+        }
+    }
+
+    /// Adds `fuel` and runs until the program ends or the fuel runs out.
+    /// After `ExitStatus::OutOfFuel`, calling this again resumes execution.
+    pub fn run_with_fuel<T: AgarIo>(&mut self, fuel: u64, io: &mut T) -> ExitStatus {
+        self.add_fuel(fuel);
+        self.run(io)
+    }
+
+    /// Input for the instruction at `ip`: replayed from the history or a replay
+    /// log if there is one, otherwise read by `live` and recorded.
+    fn input<F>(&mut self, live: F) -> Result<Input, StepResult>
+    where
+        F: FnOnce(&mut Self) -> Result<Input, StepResult>,
+    {
+        if let Some(input) = self.history.as_mut().and_then(|h| h.replay(self.ip)) {
+            self.path = PATH_START;
+            return Ok(input);
+        }
+        let input = if let ReplayMode::Replay(log, next) = &mut self.replay {
+            match log.events.get(*next) {
+                Some(event) if event.ip == self.ip && event.path == self.path => {
+                    *next += 1;
+                    event.input.clone()
+                }
+                _ => return Err(self.error(ErrorKind::ReplayDiverged, &[])),
+            }
+        } else {
+            live(self)?
+        };
+        if let ReplayMode::Record(log) = &mut self.replay {
+            log.events.push(Event {
+                ip: self.ip,
+                path: self.path,
+                input: input.clone(),
+            });
+        }
+        if let Some(history) = &mut self.history {
+            history.record(self.ip, self.path, &input);
+        }
+        self.path = PATH_START;
+        Ok(input)
+    }
+
+    /// Builds a `StepResult::Error` for the instruction at `ip`.
+    fn error(&self, kind: ErrorKind, operands: &[Data]) -> StepResult {
+        self.fail(RuntimeError::new(kind).with_operands(operands))
+    }
+
+    /// Fills in where `error` happened.
+    fn fail(&self, mut error: RuntimeError) -> StepResult {
+        error.ip = self.ip;
+        error.op_code = self.program.get(self.ip).map(|instr| instr.op_code);
+        error.backtrace = self.frames.iter().rev().copied().collect();
+        if let Some(debug) = &self.program.debug {
+            error.file = Some(debug.file.clone());
+            error.location = debug.location(self.ip);
         }
+        StepResult::Error(error)
     }
 
     pub fn goto(&mut self, ip: usize) {
+        // Jumping anywhere breaks what the verifier proved about the stack.
+        self.unchecked = false;
         self.ip = ip;
     }
 
@@ -289,8 +1072,8 @@ impl Interpreter {
         &self.stack
     }
 
-    pub fn panic<T: Write>(&mut self, output: &mut T, error: &str) {
-        if writeln!(output, "Oops...").is_err() {}
-        if writeln!(output, "Error occurs: {}", error).is_err() {}
+    pub fn panic<T: AgarIo>(&mut self, io: &mut T, error: &str) {
+        let _ = io.write_out(format_args!("Oops...\n"));
+        let _ = io.write_out(format_args!("Error occurs: {}\n", error));
     }
 }
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::fmt::Arguments;
+use std::io::{self, BufRead, Write};
+
+use agar_core::Int;
+
+/// Everything the interpreter exchanges with the outside world.
+pub trait AgarIo {
+    fn write_out(&mut self, args: Arguments) -> io::Result<()>;
+
+    fn write_err(&mut self, args: Arguments) -> io::Result<()>;
+
+    /// Reads the rest of the current line without the line terminator.
+    /// Returns `None` at the end of input.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+
+    /// Returns `None` at the end of input.
+    fn read_char(&mut self) -> io::Result<Option<char>>;
+
+    /// Reads a line and parses it as an integer. Returns `None` at the end of input.
+    fn read_int(&mut self) -> io::Result<Option<Int>> {
+        match self.read_line()? {
+            Some(line) => match line.trim().parse::<Int>() {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Can't read Int")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl<T: AgarIo + ?Sized> AgarIo for &mut T {
+    fn write_out(&mut self, args: Arguments) -> io::Result<()> {
+        (**self).write_out(args)
+    }
+
+    fn write_err(&mut self, args: Arguments) -> io::Result<()> {
+        (**self).write_err(args)
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        (**self).read_line()
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        (**self).read_char()
+    }
+
+    fn read_int(&mut self) -> io::Result<Option<Int>> {
+        (**self).read_int()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+fn take_line(pending: &mut VecDeque<char>) -> String {
+    let mut line = String::new();
+    while let Some(ch) = pending.pop_front() {
+        if ch == '\n' {
+            break;
+        }
+        line.push(ch);
+    }
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    line
+}
+
+/// Process stdio. Input is read from stdin a line at a time.
+#[derive(Debug, Default)]
+pub struct StdIo {
+    pending: VecDeque<char>,
+}
+
+impl StdIo {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Makes sure there is buffered input. Returns `false` at the end of stdin.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+            self.pending.extend(line.chars());
+        }
+        Ok(true)
+    }
+}
+
+impl AgarIo for StdIo {
+    fn write_out(&mut self, args: Arguments) -> io::Result<()> {
+        io::stdout().write_fmt(args)
+    }
+
+    fn write_err(&mut self, args: Arguments) -> io::Result<()> {
+        io::stderr().write_fmt(args)
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        Ok(Some(take_line(&mut self.pending)))
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()?;
+        io::stderr().flush()
+    }
+}
+
+/// In-memory stdio, mostly useful for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIo {
+    pub stdin: VecDeque<char>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl MemoryIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_input(input: &str) -> Self {
+        Self {
+            stdin: input.chars().collect(),
+            ..Self::default()
+        }
+    }
+}
+
+impl AgarIo for MemoryIo {
+    fn write_out(&mut self, args: Arguments) -> io::Result<()> {
+        self.stdout.write_fmt(args)
+    }
+
+    fn write_err(&mut self, args: Arguments) -> io::Result<()> {
+        self.stderr.write_fmt(args)
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        if self.stdin.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(take_line(&mut self.stdin)))
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        Ok(self.stdin.pop_front())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
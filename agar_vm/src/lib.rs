@@ -1,6 +1,29 @@
+mod code;
+mod coverage;
+mod debugger;
+mod error;
+mod history;
 mod interpreter;
+mod interrupt;
+mod io;
+mod native;
+mod profile;
+mod replay;
+mod snapshot;
+mod trace;
 
+pub use code::*;
+pub use coverage::*;
+pub use debugger::*;
+pub use error::*;
 pub use interpreter::*;
+pub use interrupt::*;
+pub use io::*;
+pub use native::*;
+pub use profile::*;
+pub use replay::*;
+pub use snapshot::*;
+pub use trace::*;
 
 #[cfg(test)]
 mod tests;
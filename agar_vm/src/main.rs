@@ -1,21 +1,215 @@
-use std::{env, path::Path, io::stdout};
+use std::{
+    cell::RefCell,
+    env,
+    fs::File,
+    io::{stderr, stdout, BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
 
 use agar_core::Program;
-use agar_vm::Interpreter;
+use agar_vm::{
+    Coverage, Debugger, EventLog, Interpreter, InterpreterConfig, JsonTracer, LoadError, Profiler,
+    StdIo, TextTracer,
+};
+
+const USAGE: &str = "Usage: agar_vm [options] <source.ab>
+
+Options:
+    --debug                start the interactive debugger
+    --verify               refuse bytecode that fails static verification
+    --trace                print every executed instruction to stderr
+    --trace-json           print the trace as JSON lines
+    --trace-depth N        number of stack slots shown in the trace (default 4)
+    --trace-out FILE       write the trace to FILE instead of stderr
+    --profile              print a hot-spot report to stderr after the run
+    --profile-top N        number of hot spots in the report (default 10)
+    --profile-folded FILE  write folded call stacks for flamegraph tools
+    --coverage FILE        write line and branch coverage to FILE in LCOV format
+    --record FILE          save every input the program receives to FILE
+    --replay FILE          feed the inputs saved by --record back to the program
+    --max-stack N          maximum operand stack depth
+    --max-call-depth N     maximum call depth
+    --max-heap N           maximum number of heap cells";
+
+#[derive(PartialEq, Eq)]
+enum Trace {
+    Off,
+    Text,
+    Json,
+}
 
 fn main() -> Result<(), ()> {
-    let args: Vec<String> = env::args().collect();
-    if let Some(raw_path) = args.get(1) {
-        let path = Path::new(raw_path);
+    let mut config = InterpreterConfig::default();
+    let mut source = None;
+    let mut debug = false;
+    let mut trace = Trace::Off;
+    let mut trace_depth = 4;
+    let mut trace_out = None;
+    let mut profile = false;
+    let mut profile_top = 10;
+    let mut profile_folded = None;
+    let mut coverage_out = None;
+    let mut record = None;
+    let mut replay = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let limit = match arg.as_str() {
+            "--max-stack" => &mut config.max_stack,
+            "--max-call-depth" => &mut config.max_call_depth,
+            "--max-heap" => &mut config.max_heap,
+            "--trace-depth" => &mut trace_depth,
+            "--profile-top" => &mut profile_top,
+            "--verify" => {
+                config.verify = true;
+                continue;
+            }
+            "--debug" => {
+                debug = true;
+                continue;
+            }
+            "--trace" => {
+                trace = Trace::Text;
+                continue;
+            }
+            "--trace-json" => {
+                trace = Trace::Json;
+                continue;
+            }
+            "--profile" => {
+                profile = true;
+                continue;
+            }
+            "--record" => {
+                record = args.next();
+                continue;
+            }
+            "--replay" => {
+                replay = args.next();
+                continue;
+            }
+            "--coverage" => {
+                coverage_out = args.next();
+                continue;
+            }
+            "--profile-folded" => {
+                profile_folded = args.next();
+                continue;
+            }
+            "--trace-out" => {
+                trace_out = args.next();
+                continue;
+            }
+            _ => {
+                source = Some(arg);
+                continue;
+            }
+        };
+        match args.next().and_then(|n| n.parse().ok()) {
+            Some(n) => *limit = n,
+            None => {
+                println!("{USAGE}");
+                return Err(());
+            }
+        }
+    }
+
+    if let Some(raw_path) = source {
+        let path = Path::new(&raw_path);
         let bytecode = std::fs::read(path).expect("Can't read bytecode");
         let program = Program::from_bytes(&bytecode).expect("Can't parse bytecode");
 
-        let mut vm = Interpreter::new();
-        vm.load_program(program);
-        match vm.run(&mut stdout()) {
-            agar_vm::ExitStatus::Error(e) => println!("RuntimeError: {e:?}"),
-            _ => {}
+        let mut vm = Interpreter::with_config(config);
+        match vm.load_program(program) {
+            Ok(()) => {}
+            Err(LoadError::Unverified(errors)) => {
+                for e in errors {
+                    println!("VerifyError: {e}");
+                }
+                return Err(());
+            }
+            Err(e) => {
+                println!("LoadError: {e:?}");
+                return Err(());
+            }
+        }
+        if trace != Trace::Off {
+            let out: Box<dyn Write> = match &trace_out {
+                Some(file) => Box::new(BufWriter::new(
+                    File::create(file).expect("Can't create trace file"),
+                )),
+                None => Box::new(stderr()),
+            };
+            if trace == Trace::Json {
+                vm.add_tracer(Box::new(JsonTracer::new(out, trace_depth)));
+            } else {
+                vm.add_tracer(Box::new(TextTracer::new(out, trace_depth)));
+            }
+        }
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        if profile || profile_folded.is_some() {
+            vm.add_tracer(Box::new(profiler.clone()));
+        }
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        if coverage_out.is_some() {
+            vm.add_tracer(Box::new(coverage.clone()));
+        }
+        if record.is_some() {
+            vm.start_recording();
+        }
+        if let Some(file) = &replay {
+            let log = std::fs::read(file).expect("Can't read replay log");
+            let log = EventLog::from_bytes(&log).and_then(|log| vm.start_replay(log));
+            if let Err(e) = log {
+                println!("ReplayError: {e:?}");
+                return Err(());
+            }
+        }
+        let mut vm = if debug {
+            let mut debugger = Debugger::new(vm);
+            debugger.source = debugger
+                .vm
+                .program
+                .debug
+                .as_ref()
+                .and_then(|debug| std::fs::read_to_string(&debug.file).ok());
+            debugger
+                .run(&mut StdIo::new(), &mut stdout())
+                .expect("Can't talk to the terminal");
+            debugger.vm
+        } else {
+            if let agar_vm::ExitStatus::Error(e) = vm.run(&mut StdIo::new()) {
+                println!("RuntimeError: {e}");
+            }
+            vm
+        };
+        if let Some(file) = record {
+            let log = vm.take_recording().unwrap_or_default();
+            std::fs::write(file, log.to_bytes()).expect("Can't write record log");
+        }
+        if let Some(remaining) = vm.replay_remaining().filter(|n| *n > 0) {
+            println!("Replay diverged: {remaining} recorded inputs were not used");
+        }
+        if profile {
+            eprint!("{}", profiler.borrow().report(&vm, profile_top));
+        }
+        if let Some(file) = profile_folded {
+            let mut out = BufWriter::new(File::create(file).expect("Can't create profile file"));
+            profiler
+                .borrow()
+                .write_folded(&vm, &mut out)
+                .expect("Can't write profile");
+        }
+        if let Some(file) = coverage_out {
+            let mut out = BufWriter::new(File::create(file).expect("Can't create coverage file"));
+            coverage
+                .borrow()
+                .write_lcov(&vm, &mut out)
+                .expect("Can't write coverage");
         }
+    } else {
+        println!("{USAGE}");
     }
 
     Ok(())
@@ -0,0 +1,34 @@
+use agar_core::{Data, VerifyError};
+
+use crate::{AgarIo, RuntimeError};
+
+/// Signature of a host function callable from bytecode with `CallNative`.
+///
+/// Arguments are passed in push order, so `args[0]` is the deepest one.
+/// The returned value is pushed on the stack.
+pub type NativeFn = fn(&mut NativeCtx, &[Data]) -> Result<Data, RuntimeError>;
+
+/// Interpreter state that a native function is allowed to touch.
+pub struct NativeCtx<'a> {
+    pub ip: usize,
+    pub io: &'a mut dyn AgarIo,
+}
+
+#[derive(Debug, Clone)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub func: NativeFn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    MissingImport(String),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// `InterpreterConfig::verify` is set and the program failed verification.
+    Unverified(Vec<VerifyError>),
+}
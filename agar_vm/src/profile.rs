@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::{Interpreter, StepResult, Tracer};
+
+/// Counts executions and time per instruction. Attach it with
+/// `Interpreter::add_tracer`, usually behind an `Rc<RefCell<_>>` so the
+/// results can be read after the run.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    counts: Vec<u64>,
+    times: Vec<Duration>,
+    /// Executed instructions per call stack, as a list of function entries.
+    stacks: HashMap<Vec<usize>, u64>,
+    started: Option<(usize, Instant)>,
+}
+
+/// One line of the hot-spot report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub ip: usize,
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of times the instruction at `ip` was executed.
+    pub fn count(&self, ip: usize) -> u64 {
+        self.counts.get(ip).copied().unwrap_or(0)
+    }
+
+    /// Total number of executed instructions.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The `n` most executed instructions, most executed first.
+    pub fn hot_spots(&self, n: usize) -> Vec<HotSpot> {
+        let mut spots: Vec<HotSpot> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(ip, count)| HotSpot {
+                ip,
+                count: *count,
+                time: self.times[ip],
+            })
+            .collect();
+        spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.ip.cmp(&b.ip)));
+        spots.truncate(n);
+        spots
+    }
+
+    /// Executions per opcode mnemonic, most executed first.
+    pub fn by_opcode(&self, vm: &Interpreter) -> Vec<(&'static str, u64)> {
+        let mut counts = BTreeMap::new();
+        for (ip, count) in self.counts.iter().enumerate() {
+            if let Some(instr) = vm.program.get(ip) {
+                *counts.entry(instr.op_code.mnemonic()).or_insert(0) += count;
+            }
+        }
+        let mut counts: Vec<_> = counts.into_iter().filter(|(_, count)| *count > 0).collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+    }
+
+    /// Executions and time per enclosing label, most time first. Instructions
+    /// before the first label are counted under `main`.
+    pub fn by_label(&self, vm: &Interpreter) -> Vec<(String, u64, Duration)> {
+        let debug = vm.program.debug.as_ref();
+        let mut labels: BTreeMap<String, (u64, Duration)> = BTreeMap::new();
+        for (ip, count) in self.counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let name = debug
+                .and_then(|debug| debug.enclosing_label(ip))
+                .map_or("main", |(name, _)| name);
+            let entry = labels.entry(name.to_string()).or_default();
+            entry.0 += count;
+            entry.1 += self.times[ip];
+        }
+        let mut labels: Vec<_> = labels
+            .into_iter()
+            .map(|(name, (count, time))| (name, count, time))
+            .collect();
+        labels.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        labels
+    }
+
+    /// Hot-spot report with the `top` most executed instructions.
+    pub fn report(&self, vm: &Interpreter, top: usize) -> String {
+        let total = self.total();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        let debug = vm.program.debug.as_ref();
+        let mut out = String::new();
+        let _ = writeln!(out, "Profile: {total} instructions executed");
+
+        let _ = writeln!(out, "\nHot spots:");
+        let _ = writeln!(out, "{:>10} {:>7} {:>10} {:>5}  instruction", "count", "%", "time", "ip");
+        for spot in self.hot_spots(top) {
+            let instr = vm
+                .program
+                .get(spot.ip)
+                .map(|instr| vm.program.format_instruction(instr))
+                .unwrap_or_default();
+            let location = debug
+                .and_then(|debug| Some((&debug.file, debug.location(spot.ip)?)))
+                .map(|(file, location)| format!("  {}:{}", file, location.line))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{:>10} {:>6.1}% {:>10.2?} {:>5}  {:<20}{}",
+                spot.count,
+                percent(spot.count),
+                spot.time,
+                spot.ip,
+                instr,
+                location
+            );
+        }
+
+        let _ = writeln!(out, "\nBy opcode:");
+        for (mnemonic, count) in self.by_opcode(vm) {
+            let _ = writeln!(out, "{:>10} {:>6.1}%  {}", count, percent(count), mnemonic);
+        }
+
+        let _ = writeln!(out, "\nBy label:");
+        for (name, count, time) in self.by_label(vm) {
+            let _ = writeln!(out, "{:>10} {:>6.1}% {:>10.2?}  {}", count, percent(count), time, name);
+        }
+        out
+    }
+
+    /// Writes the executed call stacks in the folded format read by
+    /// flamegraph tools: `main;outer;inner 42`, one stack per line.
+    pub fn write_folded<W: Write>(&self, vm: &Interpreter, out: &mut W) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut names = vec![vm.function_name(None)];
+                names.extend(stack.iter().map(|entry| vm.function_name(Some(*entry))));
+                (names.join(";"), *count)
+            })
+            .collect();
+        lines.sort();
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Tracer for Profiler {
+    fn before_step(&mut self, vm: &Interpreter) {
+        if self.counts.len() < vm.program.ops.len() {
+            self.counts.resize(vm.program.ops.len(), 0);
+            self.times.resize(vm.program.ops.len(), Duration::ZERO);
+        }
+        self.counts[vm.ip] += 1;
+        let entries: Vec<usize> = vm.frames.iter().map(|frame| frame.entry).collect();
+        *self.stacks.entry(entries).or_insert(0) += 1;
+        self.started = Some((vm.ip, Instant::now()));
+    }
+
+    fn after_step(&mut self, _vm: &Interpreter, _result: &StepResult) {
+        if let Some((ip, start)) = self.started.take() {
+            self.times[ip] += start.elapsed();
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use agar_core::container::{
+    read_data, read_sections, read_str, read_u32, read_u64, write_data, write_header,
+    write_section, write_str,
+};
+use agar_core::{Data, Int};
+
+use crate::ErrorKind;
+
+const MAGIC: &[u8; 4] = b"AGRL";
+const VERSION: u16 = 2;
+
+const SECTION_PROGRAM: u8 = 1;
+const SECTION_EVENTS: u8 = 2;
+
+/// `Event::path` before any instruction was executed.
+pub(crate) const PATH_START: u64 = 0xcbf2_9ce4_8422_2325;
+
+const INPUT_INT: u8 = 0;
+const INPUT_CHAR: u8 = 1;
+const INPUT_LINE: u8 = 2;
+const INPUT_NATIVE: u8 = 3;
+const INPUT_INVALID_INT: u8 = 4;
+
+/// Error kinds in the order they are numbered in event logs.
+const ERROR_KINDS: [ErrorKind; 9] = [
+    ErrorKind::IncompatibleType,
+    ErrorKind::NotEnoughArgs,
+    ErrorKind::InvalidValue,
+    ErrorKind::StackOverflow,
+    ErrorKind::CallDepthExceeded,
+    ErrorKind::OutOfMemory,
+    ErrorKind::ReplayDiverged,
+    ErrorKind::Other,
+    ErrorKind::EndOfInput,
+];
+
+/// A value the program received from outside the VM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// Result of `readint`; `None` at the end of input.
+    Int(Option<Int>),
+    /// A line `readint` couldn't read as a number.
+    InvalidInt,
+    /// Result of `readchar`; `None` at the end of input.
+    Char(Option<char>),
+    /// Result of `readline`; `None` at the end of input.
+    Line(Option<String>),
+    /// Value returned by a native function, or the kind of error it raised.
+    Native(Result<Data, ErrorKind>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Instruction that received the input.
+    pub ip: usize,
+    /// Hash of the instructions executed since the previous input, up to and
+    /// including this one. A replay that reaches the input on another path
+    /// diverged even though it asks at the same `ip`.
+    pub path: u64,
+    pub input: Input,
+}
+
+/// Every input of one run, tied to the program that was running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventLog {
+    /// `Program::hash` of the recorded program.
+    pub program: u64,
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    Malformed(&'static str),
+    /// The log was recorded with a different program.
+    ProgramMismatch { expected: u64, found: u64 },
+}
+
+/// Adds the instruction at `ip` to a path hash (FNV-1a over the executed ips).
+pub(crate) fn extend_path(path: u64, ip: usize) -> u64 {
+    (path ^ ip as u64).wrapping_mul(0x0100_0000_01b3)
+}
+
+/// Whether the interpreter records or replays its inputs.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum ReplayMode {
+    #[default]
+    Off,
+    Record(EventLog),
+    /// Log being replayed and the index of the next event.
+    Replay(EventLog, usize),
+}
+
+impl EventLog {
+    /// Writes the log in the bytecode container layout with `AGRL` magic.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, MAGIC, VERSION);
+        write_section(&mut bytes, SECTION_PROGRAM, &self.program.to_le_bytes());
+
+        let mut events = Vec::new();
+        for event in &self.events {
+            events.extend_from_slice(&(event.ip as u64).to_le_bytes());
+            events.extend_from_slice(&event.path.to_le_bytes());
+            encode_input(&mut events, &event.input);
+        }
+        write_section(&mut bytes, SECTION_EVENTS, &events);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut log = Self::default();
+        for (id, payload) in read_sections(bytes, MAGIC, VERSION).map_err(ReplayError::Malformed)? {
+            match id {
+                SECTION_PROGRAM => {
+                    log.program = read_u64(payload, 0)
+                        .ok_or(ReplayError::Malformed("Can't read program hash"))?
+                }
+                SECTION_EVENTS => log.events = decode_events(payload)?,
+                _ => {}
+            }
+        }
+        Ok(log)
+    }
+}
+
+fn encode_input(bytes: &mut Vec<u8>, input: &Input) {
+    match input {
+        Input::Int(n) => {
+            bytes.push(INPUT_INT);
+            match n {
+                Some(n) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&n.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+        Input::InvalidInt => {
+            bytes.push(INPUT_INVALID_INT);
+            bytes.push(0);
+        }
+        Input::Char(ch) => {
+            bytes.push(INPUT_CHAR);
+            match ch {
+                Some(ch) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&(*ch as u32).to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+        Input::Line(line) => {
+            bytes.push(INPUT_LINE);
+            match line {
+                Some(line) => {
+                    bytes.push(1);
+                    write_str(bytes, line);
+                }
+                None => bytes.push(0),
+            }
+        }
+        Input::Native(result) => {
+            bytes.push(INPUT_NATIVE);
+            match result {
+                Ok(data) => {
+                    bytes.push(1);
+                    write_data(bytes, data);
+                }
+                Err(kind) => {
+                    bytes.push(0);
+                    let index = ERROR_KINDS.iter().position(|k| k == kind).unwrap_or(0);
+                    bytes.push(index as u8);
+                }
+            }
+        }
+    }
+}
+
+fn decode_events(bytes: &[u8]) -> Result<Vec<Event>, ReplayError> {
+    let malformed = ReplayError::Malformed("Can't read event");
+    let mut events = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let ip = read_u64(bytes, i).ok_or(malformed.clone())? as usize;
+        let path = read_u64(bytes, i + 8).ok_or(malformed.clone())?;
+        let (tag, present) = match bytes.get(i + 16..i + 18) {
+            Some(slice) => (slice[0], slice[1] == 1),
+            None => return Err(malformed),
+        };
+        i += 18;
+        let input = match (tag, present) {
+            (INPUT_INT, true) => {
+                let n = read_u64(bytes, i).ok_or(malformed.clone())? as Int;
+                i += 8;
+                Input::Int(Some(n))
+            }
+            (INPUT_INT, false) => Input::Int(None),
+            (INPUT_INVALID_INT, false) => Input::InvalidInt,
+            (INPUT_CHAR, true) => {
+                let ch = read_u32(bytes, i).and_then(char::from_u32);
+                i += 4;
+                Input::Char(Some(ch.ok_or(malformed.clone())?))
+            }
+            (INPUT_CHAR, false) => Input::Char(None),
+            (INPUT_LINE, true) => {
+                let (line, next) = read_str(bytes, i).ok_or(malformed.clone())?;
+                i = next;
+                Input::Line(Some(line.to_string()))
+            }
+            (INPUT_LINE, false) => Input::Line(None),
+            (INPUT_NATIVE, true) => {
+                let (data, next) = read_data(bytes, i).ok_or(malformed.clone())?;
+                i = next;
+                Input::Native(Ok(data))
+            }
+            (INPUT_NATIVE, false) => {
+                let kind = bytes.get(i).and_then(|index| ERROR_KINDS.get(*index as usize));
+                i += 1;
+                Input::Native(Err(*kind.ok_or(malformed.clone())?))
+            }
+            _ => return Err(malformed),
+        };
+        events.push(Event { ip, path, input });
+    }
+    Ok(events)
+}
@@ -0,0 +1,180 @@
+use agar_core::container::{
+    read_data, read_sections, read_u64, write_data, write_header, write_section,
+};
+use agar_core::Data;
+
+use crate::{Frame, Interpreter};
+
+const MAGIC: &[u8; 4] = b"AGSN";
+const VERSION: u16 = 1;
+
+const SECTION_PROGRAM: u8 = 1;
+const SECTION_REGISTERS: u8 = 2;
+const SECTION_STACK: u8 = 3;
+const SECTION_FRAMES: u8 = 4;
+const SECTION_HEAP: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    Malformed(&'static str),
+    /// The snapshot was taken with a different program loaded.
+    ProgramMismatch { expected: u64, found: u64 },
+}
+
+impl Interpreter {
+    /// Serializes the execution state: `ip`, fuel, operand stack, call frames
+    /// and heap (the VM's only global storage), tagged with the hash of the
+    /// loaded program. Natives, limits and tracers are not part of a snapshot.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, MAGIC, VERSION);
+
+        write_section(&mut bytes, SECTION_PROGRAM, &self.program.hash().to_le_bytes());
+
+        let mut registers = Vec::new();
+        registers.extend_from_slice(&(self.ip as u64).to_le_bytes());
+        match self.fuel() {
+            Some(fuel) => {
+                registers.push(1);
+                registers.extend_from_slice(&fuel.to_le_bytes());
+            }
+            None => registers.push(0),
+        }
+        write_section(&mut bytes, SECTION_REGISTERS, &registers);
+
+        let mut stack = Vec::new();
+        for data in self.stack() {
+            write_data(&mut stack, data);
+        }
+        write_section(&mut bytes, SECTION_STACK, &stack);
+
+        let mut frames = Vec::new();
+        for frame in &self.frames {
+            for value in [frame.entry, frame.return_ip, frame.stack_base] {
+                frames.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+        }
+        write_section(&mut bytes, SECTION_FRAMES, &frames);
+
+        let mut heap = Vec::new();
+        for data in &self.heap {
+            write_data(&mut heap, data);
+        }
+        write_section(&mut bytes, SECTION_HEAP, &heap);
+
+        bytes
+    }
+
+    /// Restores state saved by `snapshot`. The same program must already be
+    /// loaded; on error the interpreter is left untouched. Like `goto`, this
+    /// switches `run` to the checked dispatch loop.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let sections = read_sections(bytes, MAGIC, VERSION).map_err(SnapshotError::Malformed)?;
+        let mut hash = None;
+        let mut registers = None;
+        let mut stack = Vec::new();
+        let mut frames = Vec::new();
+        let mut heap = Vec::new();
+        for (id, payload) in sections {
+            match id {
+                SECTION_PROGRAM => hash = read_u64(payload, 0),
+                SECTION_REGISTERS => registers = Some(decode_registers(payload)?),
+                SECTION_STACK => stack = decode_values(payload)?,
+                SECTION_FRAMES => frames = decode_frames(payload)?,
+                SECTION_HEAP => heap = decode_values(payload)?,
+                _ => {}
+            }
+        }
+
+        let found = hash.ok_or(SnapshotError::Malformed("Snapshot has no program hash"))?;
+        let expected = self.program.hash();
+        if found != expected {
+            return Err(SnapshotError::ProgramMismatch { expected, found });
+        }
+        let (ip, fuel) = registers.ok_or(SnapshotError::Malformed("Snapshot has no registers"))?;
+        self.check_state(ip, &stack, &frames, &heap).map_err(SnapshotError::Malformed)?;
+
+        // The state wasn't necessarily reached by running the program, so
+        // `goto` drops what the verifier proved about the stack.
+        self.goto(ip);
+        self.set_fuel(fuel);
+        self.stack = stack;
+        self.frames = frames;
+        self.heap = heap;
+        Ok(())
+    }
+
+    /// Checks that restored state fits the loaded program and the limits in
+    /// `InterpreterConfig`, like state reached by running it would.
+    fn check_state(
+        &self,
+        ip: usize,
+        stack: &[Data],
+        frames: &[Frame],
+        heap: &[Data],
+    ) -> Result<(), &'static str> {
+        let len = self.program.ops.len();
+        let config = self.config();
+        if ip > len {
+            return Err("Instruction pointer is outside the program");
+        }
+        if stack.len() > config.max_stack {
+            return Err("Operand stack is over the limit");
+        }
+        if heap.len() > config.max_heap {
+            return Err("Heap is over the limit");
+        }
+        if frames.len() > config.max_call_depth {
+            return Err("Call depth is over the limit");
+        }
+        let mut base = 0;
+        for frame in frames {
+            if frame.entry >= len || frame.return_ip == 0 || frame.return_ip > len {
+                return Err("Call frame points outside the program");
+            }
+            if frame.stack_base < base || frame.stack_base > stack.len() {
+                return Err("Call frame has an invalid stack base");
+            }
+            base = frame.stack_base;
+        }
+        Ok(())
+    }
+}
+
+fn decode_registers(bytes: &[u8]) -> Result<(usize, Option<u64>), SnapshotError> {
+    let malformed = SnapshotError::Malformed("Can't read registers");
+    let ip = read_u64(bytes, 0).ok_or(malformed.clone())? as usize;
+    let fuel = match bytes.get(8) {
+        Some(0) => None,
+        Some(1) => Some(read_u64(bytes, 9).ok_or(malformed)?),
+        _ => return Err(malformed),
+    };
+    Ok((ip, fuel))
+}
+
+fn decode_values(bytes: &[u8]) -> Result<Vec<Data>, SnapshotError> {
+    let mut values = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let (data, next) =
+            read_data(bytes, i).ok_or(SnapshotError::Malformed("Can't read value"))?;
+        values.push(data);
+        i = next;
+    }
+    Ok(values)
+}
+
+fn decode_frames(bytes: &[u8]) -> Result<Vec<Frame>, SnapshotError> {
+    if !bytes.len().is_multiple_of(24) {
+        return Err(SnapshotError::Malformed("Can't read call frames"));
+    }
+    let frames = bytes
+        .chunks(24)
+        .map(|chunk| Frame {
+            entry: read_u64(chunk, 0).unwrap_or_default() as usize,
+            return_ip: read_u64(chunk, 8).unwrap_or_default() as usize,
+            stack_base: read_u64(chunk, 16).unwrap_or_default() as usize,
+        })
+        .collect();
+    Ok(frames)
+}
@@ -9,154 +9,187 @@ mod a {
     fn add_int() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), pushi!(35), add!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"69")
+        assert_eq!(io.stdout, b"69")
     }
 
     #[test]
     fn add_float() {
         let mut vm = Interpreter::new();
         let ops = vec![pushf!("34.5"), pushf!("34.6"), add!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"69.1")
+        assert_eq!(io.stdout, b"69.1")
     }
 
     #[test]
     fn sub_int() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), pushi!(35), sub!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"-1")
+        assert_eq!(io.stdout, b"-1")
     }
 
     #[test]
     fn sub_float() {
         let mut vm = Interpreter::new();
         let ops = vec![pushf!("34.5"), pushf!("35.6"), sub!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"-1.1")
+        assert_eq!(io.stdout, b"-1.1")
     }
 
     #[test]
     fn mul_float() {
         let mut vm = Interpreter::new();
         let ops = vec![pushf!("1.5"), pushf!("2.5"), mul!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"3.75")
+        assert_eq!(io.stdout, b"3.75")
     }
 
     #[test]
     fn mul_int() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(20), pushi!(21), mul!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"420")
+        assert_eq!(io.stdout, b"420")
     }
 
     #[test]
     fn user_exit() {
         let mut vm = Interpreter::new();
         let ops = vec![pushf!("34.5"), pushf!("34.6"), exit!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![float!("34.5"), float!("34.6"),]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn add_type_error() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), pushf!("35.5"), add!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::IncompatibleType));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn sub_type_error() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), pushf!("35.5"), sub!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::IncompatibleType));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn mul_type_error() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), pushf!("35.5"), mul!(), prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::IncompatibleType));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn nop() {
         let mut vm = Interpreter::new();
         let ops = vec![nop!(), nop!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
@@ -172,40 +205,50 @@ mod a {
             prnt!(),
             nop!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"420")
+        assert_eq!(io.stdout, b"420")
     }
 
     #[test]
     fn user_panic() {
         let mut vm = Interpreter::new();
         let ops = vec![pnic!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Panic);
+        assert_eq!(io.stdout, b"Oops...\nError occurs: Panic from code\n");
     }
 
     #[test]
     fn dup() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(34), dup!(), pushf!("35.5"), dup!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"");
+        assert_eq!(io.stdout, b"");
         assert_eq!(
             *stack,
             vec![int!(34), int!(34), float!("35.5"), float!("35.5"),]
@@ -216,153 +259,186 @@ mod a {
     fn print_char() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(66), pushi!(65), printch!(), printch!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"AB")
+        assert_eq!(io.stdout, b"AB")
     }
 
     #[test]
     fn print_char_type_error() {
         let mut vm = Interpreter::new();
         let ops = vec![pushf!("35.5"), printch!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::IncompatibleType));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn add_not_enough_args1() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(100), add!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn add_not_enough_args2() {
         let mut vm = Interpreter::new();
         let ops = vec![add!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn sub_not_enough_args1() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(100), sub!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn sub_not_enough_args2() {
         let mut vm = Interpreter::new();
         let ops = vec![sub!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
     #[test]
     fn mul_not_enough_args1() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(100), mul!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn mul_not_enough_args2() {
         let mut vm = Interpreter::new();
         let ops = vec![mul!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn print_not_enough_args() {
         let mut vm = Interpreter::new();
         let ops = vec![prnt!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn print_char_not_enough_args() {
         let mut vm = Interpreter::new();
         let ops = vec![printch!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![]);
-        assert_eq!(status, ExitStatus::Error(RuntimeError::NotEnoughArgs));
-        assert_eq!(buffer, b"")
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
     fn eq_int_int() {
         let mut vm = Interpreter::new();
         let ops = vec![pushi!(60), pushi!(50), eq!(), pushi!(40), pushi!(40), eq!()];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(0), int!(1)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
@@ -376,14 +452,17 @@ mod a {
             pushi!(40),
             eq!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(0), int!(1)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
     #[test]
     fn eq_float_float() {
@@ -396,14 +475,17 @@ mod a {
             pushf!("40.0"),
             eq!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(0), int!(1)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
@@ -417,14 +499,17 @@ mod a {
             pushi!(40),
             gr!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(1), int!(0)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
@@ -438,14 +523,17 @@ mod a {
             pushi!(40),
             gr!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(1), int!(0)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
     #[test]
     fn gr_float_float() {
@@ -461,14 +549,17 @@ mod a {
             pushf!("4.1"),
             gr!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(0), int!(0), int!(1)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
     #[test]
     fn less_int_int() {
@@ -481,14 +572,17 @@ mod a {
             pushi!(40),
             less!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(1), int!(0)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
 
     #[test]
@@ -502,14 +596,17 @@ mod a {
             pushi!(40),
             less!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![int!(1), int!(0)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"")
+        assert_eq!(io.stdout, b"")
     }
     #[test]
     fn less_float_float() {
@@ -522,13 +619,1543 @@ mod a {
             pushf!("40.0"),
             less!(),
         ];
-        let mut buffer = Vec::new();
-        let program = Program { ops };
-        vm.load_program(program);
-        let status = vm.run(&mut buffer);
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
         let stack = vm.stack();
         assert_eq!(*stack, vec![Data::Int(1), Data::Int(0)]);
         assert_eq!(status, ExitStatus::Ok);
-        assert_eq!(buffer, b"");
+        assert_eq!(io.stdout, b"");
+    }
+
+    fn native_add(_ctx: &mut NativeCtx, args: &[Data]) -> Result<Data, RuntimeError> {
+        match args {
+            [Data::Int(a), Data::Int(b)] => Ok(Data::Int(a * 10 + b)),
+            _ => Err(ErrorKind::IncompatibleType.into()),
+        }
+    }
+
+    fn native_log(ctx: &mut NativeCtx, args: &[Data]) -> Result<Data, RuntimeError> {
+        if ctx.io.write_out(format_args!("log:{}", args[0])).is_err() {
+            return Err(ErrorKind::Other.into());
+        }
+        Ok(Data::Int(0))
+    }
+
+    #[test]
+    fn call_native() {
+        let mut vm = Interpreter::new();
+        vm.register_native("log", 1, native_log);
+        vm.register_native("add", 2, native_add);
+        let ops = vec![pushi!(4), pushi!(2), callnative!(1), callnative!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            imports: vec![
+                Import {
+                    name: "log".to_string(),
+                    arity: 1,
+                },
+                Import {
+                    name: "add".to_string(),
+                    arity: 2,
+                },
+            ],
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        let stack = vm.stack();
+        assert_eq!(*stack, vec![int!(0)]);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(io.stdout, b"log:42")
+    }
+
+    #[test]
+    fn call_native_error() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        let ops = vec![pushi!(4), pushf!("2.5"), callnative!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            imports: vec![Import {
+                name: "add".to_string(),
+                arity: 2,
+            }],
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+    }
+
+    #[test]
+    fn call_native_not_enough_args() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        let ops = vec![pushi!(4), callnative!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            imports: vec![Import {
+                name: "add".to_string(),
+                arity: 2,
+            }],
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+    }
+
+    #[test]
+    fn missing_import() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        let program = Program {
+            ops: vec![callnative!(0)],
+            imports: vec![Import {
+                name: "db_get".to_string(),
+                arity: 1,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            vm.load_program(program),
+            Err(LoadError::MissingImport("db_get".to_string()))
+        );
+    }
+
+    #[test]
+    fn import_arity_mismatch() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        let program = Program {
+            ops: vec![callnative!(0)],
+            imports: vec![Import {
+                name: "add".to_string(),
+                arity: 3,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            vm.load_program(program),
+            Err(LoadError::ArityMismatch {
+                name: "add".to_string(),
+                expected: 3,
+                found: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn print_err() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(7), printerr!(), pushi!(8), prnt!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(io.stdout, b"8");
+        assert_eq!(io.stderr, b"7")
+    }
+
+    #[test]
+    fn read_int() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readint!(), readint!(), add!(), prnt!()];
+        let mut io = MemoryIo::with_input("40\n 2 \n");
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(io.stdout, b"42")
+    }
+
+    #[test]
+    fn read_eof() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readchar!(), readline!(), readint!()];
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program.clone()).unwrap();
+        let status = vm.run(&mut MemoryIo::new());
+        assert_eq!(status.error_kind(), Some(ErrorKind::EndOfInput));
+        assert_eq!(*vm.stack(), vec![Data::Int(-1), Data::Int(-1)]);
+
+        // A -1 that was read is a value, not the end of input.
+        let mut vm = Interpreter::new();
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut MemoryIo::with_input("\n\n-1\n"));
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(*vm.stack(), vec![Data::Int(10), Data::Int(0), Data::Int(-1)]);
+    }
+
+    #[test]
+    fn read_int_invalid() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readint!(), readint!()];
+        let mut io = MemoryIo::with_input("forty\n");
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+    }
+
+    #[test]
+    fn read_char() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readchar!(), readchar!(), readchar!()];
+        let mut io = MemoryIo::with_input("a😀");
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        let stack = vm.stack();
+        assert_eq!(*stack, vec![int!(97), int!(128512), Data::Int(-1)]);
+        assert_eq!(status, ExitStatus::Ok);
+    }
+
+    #[test]
+    fn read_line() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readline!(), readline!(), readline!()];
+        let mut io = MemoryIo::with_input("hi\r\n\n");
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        let stack = vm.stack();
+        assert_eq!(*stack, vec![int!(105), int!(104), int!(2), int!(0), Data::Int(-1)]);
+        assert_eq!(status, ExitStatus::Ok);
+    }
+
+    #[test]
+    fn fuel_infinite_loop() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(1), jump!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run_with_fuel(11, &mut io);
+        assert_eq!(status, ExitStatus::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(0));
+        assert_eq!(vm.stack().len(), 6);
+        assert_eq!(vm.ip, 1);
+    }
+
+    #[test]
+    fn fuel_resume() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(20), pushi!(21), mul!(), prnt!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run_with_fuel(7, &mut io);
+        assert_eq!(status, ExitStatus::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(4));
+        assert_eq!(vm.ip, 3);
+        assert_eq!(io.stdout, b"");
+        let status = vm.run_with_fuel(2, &mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(vm.fuel(), Some(1));
+        assert_eq!(io.stdout, b"420");
+    }
+
+    #[test]
+    fn fuel_cost_table() {
+        let mut vm = Interpreter::new();
+        let mut costs = CostTable::uniform(2);
+        costs.set(OpCode::Nop, 0);
+        vm.set_cost_table(costs);
+        let ops = vec![nop!(), nop!(), pushi!(1), pushi!(2)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run_with_fuel(3, &mut io);
+        assert_eq!(status, ExitStatus::OutOfFuel);
+        assert_eq!(vm.fuel(), Some(1));
+        assert_eq!(*vm.stack(), vec![int!(1)]);
+    }
+
+    #[test]
+    fn call_ret() {
+        let mut vm = Interpreter::new();
+        let ops = vec![
+            pushi!(20),
+            call!(4),
+            prnt!(),
+            exit!(),
+            pushi!(21),
+            mul!(),
+            ret!(),
+        ];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(vm.frames, vec![]);
+        assert_eq!(io.stdout, b"420")
+    }
+
+    #[test]
+    fn ret_without_call() {
+        let mut vm = Interpreter::new();
+        let ops = vec![ret!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+    }
+
+    #[test]
+    fn heap_store_load() {
+        let mut vm = Interpreter::new();
+        let ops = vec![
+            pushi!(2),
+            alloc!(),
+            dup!(),
+            pushi!(42),
+            store!(),
+            load!(),
+            prnt!(),
+        ];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(vm.heap, vec![int!(42), int!(0)]);
+        assert_eq!(io.stdout, b"42")
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut vm = Interpreter::with_config(InterpreterConfig {
+            max_stack: 8,
+            ..Default::default()
+        });
+        let ops = vec![pushi!(1), dup!(), jump!(1)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::StackOverflow));
+        assert_eq!(vm.ip, 1);
+        assert_eq!(vm.stack.len(), 8);
+    }
+
+    #[test]
+    fn read_line_overflow() {
+        let mut vm = Interpreter::with_config(InterpreterConfig {
+            max_stack: 8,
+            ..Default::default()
+        });
+        let ops = vec![pushi!(1), readline!()];
+        let mut io = MemoryIo::with_input("a long line\n");
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::StackOverflow));
+        assert_eq!(vm.ip, 1);
+        assert_eq!(*vm.stack(), vec![int!(1)]);
+    }
+
+    #[test]
+    fn call_depth_exceeded() {
+        let mut vm = Interpreter::with_config(InterpreterConfig {
+            max_call_depth: 16,
+            ..Default::default()
+        });
+        let ops = vec![nop!(), call!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::CallDepthExceeded));
+        assert_eq!(vm.frames.len(), 16);
+        assert_eq!(vm.ip, 1);
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let mut vm = Interpreter::with_config(InterpreterConfig {
+            max_heap: 10,
+            ..Default::default()
+        });
+        let ops = vec![pushi!(6), alloc!(), pushi!(6), alloc!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::OutOfMemory));
+        assert_eq!(vm.heap.len(), 6);
+        assert_eq!(vm.ip, 3);
+    }
+
+    #[test]
+    fn interrupt_before_run() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(1), prnt!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        vm.interrupt_handle().interrupt();
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Interrupted);
+        assert_eq!(vm.ip, 0);
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(io.stdout, b"1");
+    }
+
+    #[test]
+    fn interrupt_from_thread() {
+        let mut vm = Interpreter::new();
+        let ops = vec![nop!(), jump!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let handle = vm.interrupt_handle();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.interrupt();
+        });
+        let status = vm.run(&mut io);
+        thread.join().unwrap();
+        assert_eq!(status, ExitStatus::Interrupted);
+        assert!(!vm.interrupt_handle().is_interrupted());
+        let status = vm.run_with_fuel(3, &mut io);
+        assert_eq!(status, ExitStatus::OutOfFuel);
+    }
+
+    #[test]
+    fn error_context() {
+        let mut vm = Interpreter::new();
+        let ops = vec![
+            pushi!(34),
+            call!(3),
+            exit!(),
+            pushf!("35.5"),
+            add!(),
+            ret!(),
+        ];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        let error = match status {
+            ExitStatus::Error(e) => e,
+            _ => unreachable!("expected runtime error"),
+        };
+        assert_eq!(error.kind, ErrorKind::IncompatibleType);
+        assert_eq!(error.ip, 4);
+        assert_eq!(error.op_code, Some(OpCode::Add));
+        assert_eq!(error.operands, vec!["Int", "Float"]);
+        assert_eq!(
+            error.backtrace,
+            vec![Frame {
+                entry: 3,
+                return_ip: 2,
+                stack_base: 1,
+            }]
+        );
+        assert_eq!(
+            error.to_string(),
+            "incompatible operand types at ip 4: Add on Int and Float\n    in function at 3 called from ip 1"
+        );
+    }
+
+    #[test]
+    fn native_error_context() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        let ops = vec![pushi!(4), pushf!("2.5"), callnative!(0)];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            imports: vec![Import {
+                name: "add".to_string(),
+                arity: 2,
+            }],
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(
+            status.to_string(),
+            "runtime error: incompatible operand types at ip 2: CallNative"
+        );
+    }
+
+    #[test]
+    fn error_source_location() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(34), pushf!("35.5"), add!()];
+        let mut io = MemoryIo::new();
+        let program = Program {
+            ops,
+            debug: Some(DebugInfo {
+                file: "sum.aa".to_string(),
+                locations: vec![
+                    SourceLocation { line: 1, column: 1 },
+                    SourceLocation { line: 2, column: 1 },
+                    SourceLocation { line: 4, column: 5 },
+                ],
+                labels: vec![],
+            }),
+            ..Default::default()
+        };
+        vm.load_program(program).unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(
+            status.to_string(),
+            "runtime error: incompatible operand types at ip 2 (sum.aa:4:5): Add on Int and Float"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct IpTracer(std::rc::Rc<std::cell::RefCell<Vec<usize>>>);
+
+    impl Tracer for IpTracer {
+        fn before_step(&mut self, vm: &Interpreter) {
+            self.0.borrow_mut().push(vm.ip);
+        }
+    }
+
+    #[test]
+    fn tracer_hooks() {
+        let mut vm = Interpreter::new();
+        let ips = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        vm.add_tracer(Box::new(IpTracer(ips.clone())));
+        let ops = vec![pushi!(1), jump!(3), pushi!(2), prnt!()];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.run(&mut io);
+        assert_eq!(*ips.borrow(), vec![0, 1, 3]);
+        assert_eq!(io.stdout, b"1");
+        assert_eq!(vm.take_tracers().len(), 1);
+    }
+
+    #[test]
+    fn text_tracer() {
+        let mut vm = Interpreter::new();
+        let buf = SharedBuf::default();
+        vm.set_tracer(Box::new(TextTracer::new(Box::new(buf.clone()), 2)));
+        let ops = vec![pushi!(1), pushi!(2), pushi!(3), pushf!("0.5"), add!()];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.run(&mut io);
+        let trace = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].trim_end(), "    0  pushi 1              []");
+        assert_eq!(lines[3].trim_end(), "    3  pushf 0.5            [.. 2 3]");
+        assert!(lines[5].contains("IncompatibleType"));
+    }
+
+    #[test]
+    fn json_tracer() {
+        let mut vm = Interpreter::new();
+        let buf = SharedBuf::default();
+        vm.set_tracer(Box::new(JsonTracer::new(Box::new(buf.clone()), 4)));
+        let ops = vec![pushi!(7), pushf!("1.5"), add!()];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.run(&mut io);
+        let trace = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        assert_eq!(
+            trace,
+            "{\"step\":0,\"ip\":0,\"op\":\"pushi\",\"arg\":7,\"depth\":0,\"stack\":[]}\n\
+             {\"step\":1,\"ip\":1,\"op\":\"pushf\",\"arg\":\"1.5\",\"depth\":1,\"stack\":[7]}\n\
+             {\"step\":2,\"ip\":2,\"op\":\"add\",\"arg\":null,\"depth\":2,\"stack\":[7,\"1.5\"]}\n\
+             {\"error\":\"incompatible operand types at ip 2: Add on Int and Float\"}\n"
+        );
+    }
+
+    #[test]
+    fn profiler_counts() {
+        let mut vm = Interpreter::new();
+        let profiler = std::rc::Rc::new(std::cell::RefCell::new(Profiler::new()));
+        vm.add_tracer(Box::new(profiler.clone()));
+        // 0: pushi 3; 1: call 3; 2: exit; 3: pushi 1; 4: sub; 5: cjump 3; 6: ret
+        let ops = vec![
+            pushi!(3),
+            call!(3),
+            exit!(),
+            pushi!(1),
+            sub!(),
+            cjump!(3),
+            ret!(),
+        ];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+
+        let profiler = profiler.borrow();
+        assert_eq!(profiler.total(), 13);
+        assert_eq!(profiler.count(4), 3);
+        assert_eq!(profiler.count(6), 1);
+        let spots: Vec<(usize, u64)> = profiler
+            .hot_spots(3)
+            .iter()
+            .map(|spot| (spot.ip, spot.count))
+            .collect();
+        assert_eq!(spots, vec![(3, 3), (4, 3), (5, 3)]);
+        assert_eq!(profiler.by_opcode(&vm)[0], ("pushi", 4));
+
+        let mut folded = Vec::new();
+        profiler.write_folded(&vm, &mut folded).unwrap();
+        assert_eq!(folded, b"main 3\nmain;function@3 10\n");
+    }
+
+    #[test]
+    fn coverage_lcov() {
+        let mut vm = Interpreter::new();
+        let coverage = std::rc::Rc::new(std::cell::RefCell::new(Coverage::new()));
+        vm.add_tracer(Box::new(coverage.clone()));
+        // 0: pushi 2; 1: pushi 1; 2: sub; 3: cjump 1; 4: exit; 5: prnt
+        let ops = vec![pushi!(2), pushi!(1), sub!(), cjump!(1), exit!(), prnt!()];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            debug: Some(DebugInfo {
+                file: "count.aa".to_string(),
+                locations: vec![
+                    SourceLocation { line: 1, column: 1 },
+                    SourceLocation { line: 2, column: 1 },
+                    SourceLocation { line: 2, column: 9 },
+                    SourceLocation { line: 3, column: 1 },
+                    SourceLocation { line: 4, column: 1 },
+                    SourceLocation { line: 5, column: 1 },
+                ],
+                labels: vec![],
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+
+        let coverage = coverage.borrow();
+        assert_eq!(coverage.hits(1), 2);
+        assert_eq!(coverage.hits(5), 0);
+        assert_eq!(coverage.branch(3), Some((1, 1)));
+
+        let mut lcov = Vec::new();
+        coverage.write_lcov(&vm, &mut lcov).unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:\nSF:count.aa\nFNF:0\nFNH:0\n\
+             BRDA:3,3,0,1\nBRDA:3,3,1,1\nBRF:2\nBRH:2\n\
+             DA:1,1\nDA:2,2\nDA:3,2\nDA:4,1\nDA:5,0\nLF:5\nLH:4\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        // Allocates a heap cell, then counts down from 3 printing each value.
+        let ops = vec![
+            pushi!(1),
+            alloc!(),
+            pushi!(3),
+            dup!(),
+            prnt!(),
+            pushi!(1),
+            sub!(),
+            dup!(),
+            cjump!(3),
+        ];
+        let program = Program {
+            ops,
+            ..Default::default()
+        };
+
+        let mut vm = Interpreter::new();
+        vm.load_program(program.clone()).unwrap();
+        vm.set_fuel(Some(100));
+        let mut io = MemoryIo::new();
+        for _ in 0..8 {
+            assert_eq!(vm.step(&mut io), StepResult::Ok);
+        }
+        assert_eq!(io.stdout, b"3");
+        let snapshot = vm.snapshot();
+
+        let mut resumed = Interpreter::new();
+        resumed.load_program(program).unwrap();
+        resumed.restore(&snapshot).unwrap();
+        assert_eq!(resumed.ip, vm.ip);
+        assert_eq!(resumed.stack(), vm.stack());
+        assert_eq!(resumed.heap, vm.heap);
+        assert_eq!(resumed.fuel(), vm.fuel());
+
+        let mut io = MemoryIo::new();
+        assert_eq!(resumed.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"21");
+    }
+
+    #[test]
+    fn snapshot_program_mismatch() {
+        let mut vm = Interpreter::new();
+        vm.load_program(Program {
+            ops: vec![pushi!(1), prnt!()],
+            ..Default::default()
+        })
+        .unwrap();
+        let snapshot = vm.snapshot();
+
+        let mut other = Interpreter::new();
+        other
+            .load_program(Program {
+                ops: vec![pushi!(2), prnt!()],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::ProgramMismatch { .. })
+        ));
+        assert!(matches!(
+            other.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Malformed(_))
+        ));
+        assert!(matches!(
+            other.restore(&Program::new().to_bytes()),
+            Err(SnapshotError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn snapshot_out_of_range() {
+        let program = Program {
+            ops: vec![pushi!(1), call!(3), exit!(), ret!()],
+            ..Default::default()
+        };
+        let mut vm = Interpreter::new();
+        vm.load_program(program.clone()).unwrap();
+        let mut target = Interpreter::with_config(InterpreterConfig {
+            max_stack: 4,
+            ..Default::default()
+        });
+        target.load_program(program).unwrap();
+        let frame = Frame {
+            entry: 3,
+            return_ip: 2,
+            stack_base: 1,
+        };
+        vm.stack = vec![int!(1)];
+        vm.frames = vec![frame];
+        target.restore(&vm.snapshot()).unwrap();
+
+        let bad_frames = [
+            Frame { entry: 9, ..frame },
+            Frame { return_ip: 0, ..frame },
+            Frame { return_ip: 5, ..frame },
+            Frame { stack_base: 2, ..frame },
+        ];
+        for bad in bad_frames {
+            vm.frames = vec![bad];
+            let result = target.restore(&vm.snapshot());
+            assert!(matches!(result, Err(SnapshotError::Malformed(_))), "{bad:?}");
+        }
+        vm.frames = vec![frame, Frame { stack_base: 0, ..frame }];
+        assert!(matches!(target.restore(&vm.snapshot()), Err(SnapshotError::Malformed(_))));
+
+        vm.frames.clear();
+        vm.ip = 5;
+        assert!(matches!(target.restore(&vm.snapshot()), Err(SnapshotError::Malformed(_))));
+        vm.ip = 0;
+        vm.stack = vec![int!(1); 5];
+        assert!(matches!(target.restore(&vm.snapshot()), Err(SnapshotError::Malformed(_))));
+        // Nothing was applied by the failed restores.
+        assert_eq!(target.frames, vec![frame]);
+
+        let mut error = RuntimeError::new(ErrorKind::Other);
+        error.backtrace = vec![Frame { return_ip: 0, ..frame }];
+        assert!(error.to_string().ends_with("called from ip 0"));
+    }
+
+    fn replay_program() -> Program {
+        Program {
+            ops: vec![readint!(), readchar!(), callnative!(0), prnt!(), readline!(), prnt!()],
+            imports: vec![Import {
+                name: "add".to_string(),
+                arity: 2,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn record_replay() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        vm.load_program(replay_program()).unwrap();
+        vm.start_recording();
+        let mut io = MemoryIo::with_input("4\n2");
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"90-1");
+        let log = vm.take_recording().unwrap();
+        assert_eq!(log.events.len(), 4);
+        assert_eq!(
+            (log.events[1].ip, &log.events[1].input),
+            (1, &Input::Char(Some('2')))
+        );
+        assert_ne!(log.events[0].path, log.events[1].path);
+        let log = EventLog::from_bytes(&log.to_bytes()).unwrap();
+
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        vm.load_program(replay_program()).unwrap();
+        vm.start_replay(log).unwrap();
+        let mut io = MemoryIo::new();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"90-1");
+        assert_eq!(vm.replay_remaining(), Some(0));
+        let inputs = [Input::Int(Some(-1)), Input::Int(None), Input::InvalidInt];
+        let log = EventLog {
+            program: 1,
+            events: inputs
+                .into_iter()
+                .map(|input| Event { ip: 0, path: 0, input })
+                .collect(),
+        };
+        assert_eq!(EventLog::from_bytes(&log.to_bytes()), Ok(log));
+    }
+
+    #[test]
+    fn replay_divergence() {
+        let mut vm = Interpreter::new();
+        vm.register_native("add", 2, native_add);
+        vm.load_program(replay_program()).unwrap();
+        let log = EventLog {
+            program: vm.program.hash(),
+            events: vec![Event {
+                ip: 1,
+                path: 0,
+                input: Input::Char(Some('2')),
+            }],
+        };
+        vm.start_replay(log.clone()).unwrap();
+        let status = vm.run(&mut MemoryIo::new());
+        assert_eq!(status.error_kind(), Some(ErrorKind::ReplayDiverged));
+        if let ExitStatus::Error(e) = status {
+            assert_eq!(e.ip, 0);
+        }
+
+        let mut other = Interpreter::new();
+        other
+            .load_program(Program {
+                ops: vec![readint!()],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(matches!(
+            other.start_replay(log),
+            Err(ReplayError::ProgramMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn replay_path_divergence() {
+        // Both runs read at ip 2, but only the recorded one went through the `nop`.
+        let program = Program {
+            ops: vec![cjump!(2), nop!(), readint!(), prnt!()],
+            ..Default::default()
+        };
+        let mut vm = Interpreter::new();
+        vm.load_program(program.clone()).unwrap();
+        vm.stack = vec![int!(0)];
+        vm.start_recording();
+        assert_eq!(vm.run(&mut MemoryIo::with_input("7")), ExitStatus::Ok);
+        let log = vm.take_recording().unwrap();
+
+        let mut vm = Interpreter::new();
+        vm.load_program(program).unwrap();
+        vm.stack = vec![int!(0)];
+        vm.start_replay(log.clone()).unwrap();
+        let mut io = MemoryIo::new();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"7");
+
+        vm.ip = 0;
+        vm.stack = vec![int!(1)];
+        vm.start_replay(log).unwrap();
+        let status = vm.run(&mut MemoryIo::new());
+        assert_eq!(status.error_kind(), Some(ErrorKind::ReplayDiverged));
+        if let ExitStatus::Error(e) = status {
+            assert_eq!(e.ip, 2);
+        }
+    }
+
+    #[test]
+    fn step_back() {
+        let mut vm = Interpreter::new();
+        let ops = vec![readint!(), pushi!(1), add!(), dup!(), prnt!(), readchar!(), add!()];
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        vm.enable_history(2);
+        assert!(!vm.step_back());
+
+        let mut io = MemoryIo::with_input("41\na");
+        let mut states = Vec::new();
+        for _ in 0..7 {
+            states.push((vm.ip, vm.stack().clone()));
+            assert_eq!(vm.step(&mut io), StepResult::Ok);
+        }
+        assert_eq!(vm.steps_executed(), Some(7));
+        assert_eq!(io.stdout, b"42");
+
+        for (ip, stack) in states.iter().rev() {
+            assert!(vm.step_back());
+            assert_eq!((&vm.ip, vm.stack()), (ip, stack));
+        }
+        assert!(!vm.step_back());
+        assert_eq!(vm.steps_executed(), Some(0));
+
+        // Running forward again reuses the recorded input instead of reading more.
+        let status = vm.run(&mut io);
+        assert_eq!(status, ExitStatus::Ok);
+        assert_eq!(vm.stack(), &vec![Data::Int(42 + 'a' as i64)]);
+        assert_eq!(io.stdout, b"4242");
+    }
+
+    #[test]
+    fn pop() {
+        let mut vm = Interpreter::new();
+        let ops = vec![pushi!(1), pushi!(2), pop!(), prnt!(), pop!()];
+        let mut io = MemoryIo::new();
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        let status = vm.run(&mut io);
+        assert_eq!(io.stdout, b"1");
+        assert_eq!(status.error_kind(), Some(ErrorKind::NotEnoughArgs));
+    }
+
+    #[test]
+    fn load_verified_only() {
+        let mut vm = Interpreter::with_config(InterpreterConfig {
+            verify: true,
+            ..Default::default()
+        });
+        let program = Program {
+            ops: vec![pushi!(1), jump!(7)],
+            ..Default::default()
+        };
+        match vm.load_program(program) {
+            Err(LoadError::Unverified(errors)) => {
+                assert_eq!(errors[0].kind, VerifyErrorKind::TargetOutOfRange(7));
+            }
+            other => unreachable!("{other:?}"),
+        }
+
+        let program = Program {
+            ops: vec![pushi!(1), prnt!()],
+            ..Default::default()
+        };
+        vm.load_program(program.clone()).unwrap();
+        let mut vm = Interpreter::new();
+        vm.load_verified(verify(&program).unwrap()).unwrap();
+        let mut io = MemoryIo::new();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"1");
+    }
+
+    #[test]
+    fn unchecked_short_stack() {
+        let program = Program {
+            ops: vec![pushi!(1), pushi!(2), add!(), prnt!()],
+            ..Default::default()
+        };
+        let not_enough_args = |status| match status {
+            ExitStatus::Error(e) => assert_eq!((e.kind, e.ip), (ErrorKind::NotEnoughArgs, 2)),
+            other => unreachable!("{other:?}"),
+        };
+
+        // A snapshot taken in a checked interpreter at `add` with an empty stack.
+        let mut checked = Interpreter::new();
+        checked.load_program(program.clone()).unwrap();
+        checked.ip = 2;
+        let mut vm = Interpreter::new();
+        vm.load_verified(verify(&program).unwrap()).unwrap();
+        vm.restore(&checked.snapshot()).unwrap();
+        not_enough_args(vm.run(&mut MemoryIo::new()));
+
+        // State changed directly, in front of a superinstruction.
+        let mut vm = Interpreter::new();
+        vm.load_verified(verify(&program).unwrap()).unwrap();
+        assert_eq!(vm.code().ops[1], Op::AddImm(2));
+        vm.ip = 1;
+        not_enough_args(vm.run(&mut MemoryIo::new()));
+    }
+
+    fn native_half(_ctx: &mut NativeCtx, _args: &[Data]) -> Result<Data, RuntimeError> {
+        Ok(float!("0.5"))
+    }
+
+    /// Runs `ops` in the checked interpreter and in the unchecked loop for
+    /// verified programs, and checks that both end in the same state.
+    fn differential(ops: Vec<Instruction>, input: &str, config: InterpreterConfig) -> ExitStatus {
+        let program = Program {
+            ops,
+            imports: vec![Import {
+                name: "half".to_string(),
+                arity: 0,
+            }],
+            ..Default::default()
+        };
+        let verified = verify(&program).unwrap();
+
+        let mut checked = Interpreter::with_config(config);
+        checked.register_native("half", 0, native_half);
+        checked.load_program(program).unwrap();
+        let mut checked_io = MemoryIo::with_input(input);
+        let checked_status = checked.run(&mut checked_io);
+
+        let mut fast = Interpreter::with_config(config);
+        fast.register_native("half", 0, native_half);
+        fast.load_verified(verified).unwrap();
+        let mut fast_io = MemoryIo::with_input(input);
+        let fast_status = fast.run(&mut fast_io);
+
+        assert_eq!(checked_status, fast_status);
+        assert_eq!(checked_io.stdout, fast_io.stdout);
+        assert_eq!(checked_io.stderr, fast_io.stderr);
+        assert_eq!(checked.stack(), fast.stack());
+        assert_eq!(checked.heap, fast.heap);
+        assert_eq!((checked.ip, &checked.frames), (fast.ip, &fast.frames));
+        fast_status
+    }
+
+    #[test]
+    fn differential_programs() {
+        let config = InterpreterConfig::default();
+        // Countdown printing through a function.
+        let countdown = vec![
+            pushi!(3),
+            pushi!(0),
+            pop!(),
+            dup!(),
+            call!(12),
+            pushi!(1),
+            sub!(),
+            dup!(),
+            not!(),
+            pop!(),
+            cjump!(2),
+            exit!(),
+            prnt!(),
+            ret!(),
+        ];
+        assert_eq!(differential(countdown, "", config), ExitStatus::Ok);
+
+        let heap = vec![
+            pushi!(2),
+            alloc!(),
+            dup!(),
+            pushi!(1),
+            add!(),
+            pushi!(7),
+            store!(),
+            dup!(),
+            pushi!(5),
+            add!(),
+            pushi!(7),
+            store!(),
+            dup!(),
+            load!(),
+            prnt!(),
+        ];
+        let status = differential(heap, "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+
+        let input = vec![readint!(), readchar!(), gr!(), dup!(), prnt!(), pushi!(1), eq!(), prnt!()];
+        assert_eq!(differential(input, "5\nA", config), ExitStatus::Ok);
+
+        // Types the verifier can't know fail the same way at runtime.
+        let native = vec![pushi!(1), callnative!(0), add!()];
+        let status = differential(native, "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        let native = vec![callnative!(0), cjump!(2)];
+        let status = differential(native, "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+
+        let overflow = vec![pushi!(1), dup!(), dup!(), pushf!("1.5"), pushi!(2)];
+        let config = InterpreterConfig {
+            max_stack: 4,
+            ..Default::default()
+        };
+        let status = differential(overflow, "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::StackOverflow));
+
+        let status = differential(vec![pushi!(1), pnic!()], "", config);
+        assert_eq!(status, ExitStatus::Panic);
+
+        // Not underflow-free, so this runs checked in both cases.
+        let line = vec![readline!(), prnt!(), prnt!()];
+        assert_eq!(differential(line, "hi\n", config), ExitStatus::Ok);
+    }
+
+    #[test]
+    fn superinstructions() {
+        let config = InterpreterConfig::default();
+        let ops = vec![
+            pushi!(5),
+            pushi!(1),
+            sub!(),
+            dup!(),
+            prnt!(),
+            dup!(),
+            pushi!(0),
+            eq!(),
+            cjump!(11),
+            pop!(),
+            jump!(1),
+            pop!(),
+            pushi!(2),
+            add!(),
+            dup!(),
+            not!(),
+            cjump!(21),
+            pop!(),
+            dup!(),
+            cjump!(21),
+            exit!(),
+            prnt!(),
+            prnt!(),
+        ];
+        let program = Program {
+            ops: ops.clone(),
+            ..Default::default()
+        };
+        let mut vm = Interpreter::new();
+        vm.load_verified(verify(&program).unwrap()).unwrap();
+        let code = vm.code();
+        assert_eq!(code.ops[1], Op::SubImm(1));
+        assert_eq!(code.ops[6], Op::EqZeroCJump(11));
+        assert_eq!(code.ops[7], Op::Eq);
+        assert_eq!(code.ops[12], Op::AddImm(2));
+        assert_eq!(code.ops[15], Op::NotCJump(21));
+        assert_eq!(code.ops[18], Op::DupCJump(21));
+        assert_eq!(code.ops[6].op_code(), OpCode::PushInt);
+
+        // Stepping runs one instruction at a time.
+        let mut io = MemoryIo::new();
+        vm.step(&mut io);
+        vm.step(&mut io);
+        assert_eq!((vm.ip, vm.stack().len()), (2, 2));
+
+        let mut io = MemoryIo::new();
+        assert_eq!(differential(ops, "", config), ExitStatus::Ok);
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(io.stdout, b"4321022");
+
+        // Errors are reported at the instruction that fails, not the fused one.
+        let status = differential(vec![callnative!(0), pushi!(1), add!()], "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::IncompatibleType));
+        let status = differential(vec![callnative!(0), dup!(), cjump!(3)], "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+
+        let config = InterpreterConfig {
+            max_stack: 2,
+            ..Default::default()
+        };
+        let overflow = vec![pushi!(1), pushi!(1), pushi!(1), add!()];
+        let status = differential(overflow, "", config);
+        assert_eq!(status.error_kind(), Some(ErrorKind::StackOverflow));
+    }
+
+    #[test]
+    fn lowered_code() {
+        assert_eq!(std::mem::size_of::<Op>(), 8);
+
+        let mut vm = Interpreter::new();
+        let ops = vec![
+            pushi!(7),
+            pushi!(1099511627776),
+            pushf!("2.5"),
+            Instruction {
+                op_code: OpCode::Jump,
+                operands: Operands::One(Data::Int(-1)),
+            },
+            Instruction {
+                op_code: OpCode::Call,
+                operands: Operands::Zero,
+            },
+        ];
+        vm.load_program(Program {
+            ops,
+            ..Default::default()
+        })
+        .unwrap();
+        let code = vm.code();
+        assert_eq!(
+            code.ops,
+            vec![
+                Op::PushSmall(7),
+                Op::PushConst(0),
+                Op::PushFloat(1),
+                Op::Jump(u32::MAX),
+                Op::Malformed(OpCode::Call),
+            ]
+        );
+        assert_eq!(code.consts[0], Data::Int(1 << 40));
+        assert_eq!(code.ops[2].op_code(), OpCode::PushFloat);
+
+        let mut io = MemoryIo::new();
+        assert_eq!(vm.run(&mut io), ExitStatus::Ok);
+        assert_eq!(
+            vm.stack(),
+            &vec![Data::Int(7), Data::Int(1 << 40), Data::Float("2.5".parse().unwrap())]
+        );
+
+        vm.goto(4);
+        let status = vm.run(&mut io);
+        assert_eq!(status.error_kind(), Some(ErrorKind::InvalidValue));
+    }
+}
+
+mod debugger {
+    use agar_asm::Assembler;
+
+    use crate::*;
+
+    fn debug_session(source: &str, commands: &str) -> (Debugger, String, MemoryIo) {
+        let mut asm = Assembler::new(source.to_string());
+        asm.file = "test.aa".to_string();
+        let program = asm.parse_source().unwrap();
+        let mut vm = Interpreter::new();
+        vm.load_program(program).unwrap();
+        let mut debugger = Debugger::new(vm);
+        debugger.source = Some(source.to_string());
+        let mut io = MemoryIo::with_input(commands);
+        let mut out = Vec::new();
+        debugger.run(&mut io, &mut out).unwrap();
+        (debugger, String::from_utf8(out).unwrap(), io)
+    }
+
+    const COUNTDOWN: &str = "        pushi 2
+loop:   dup
+        call show
+        pushi 1
+        sub
+        dup
+        cjump loop
+        exit
+show:   print
+        ret
+";
+
+    #[test]
+    fn program_input_between_commands() {
+        let source = "readint\nreadint\nadd\nprint\n";
+        let (debugger, out, io) = debug_session(source, "step\n40\nnext\n2\ncontinue\n");
+        assert!(out.contains("-> 1: readint"));
+        assert!(out.contains("Program finished: ok"));
+        assert_eq!(debugger.status(), Some(&ExitStatus::Ok));
+        assert_eq!(io.stdout, b"42");
+    }
+
+    #[test]
+    fn step_and_stack() {
+        let (debugger, out, _) = debug_session(COUNTDOWN, "step\nstep 2\nstack\nlocals\n");
+        assert_eq!(debugger.vm.ip, 8);
+        assert!(out.contains("-> 0: pushi 2  (test.aa:1)\n   1 |         pushi 2"));
+        assert!(out.contains("-> 8: print  (test.aa:9)"));
+        assert!(out.contains("[1] 2 (Int)\n[0] 2 (Int)"));
+        assert!(out.contains("<empty>"));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (debugger, out, io) =
+            debug_session(COUNTDOWN, "break show\nbreak :4\ncontinue\ncontinue\ncontinue\n");
+        assert!(out.contains("Breakpoint 1 at ip 8"));
+        assert!(out.contains("Breakpoint 2 at ip 3"));
+        assert!(out.contains("Breakpoint 1\n-> 8: print"));
+        assert!(out.contains("Breakpoint 2\n-> 3: pushi 1"));
+        assert_eq!(debugger.vm.ip, 8);
+        assert_eq!(io.stdout, b"2");
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (debugger, out, io) = debug_session(COUNTDOWN, "next\nnext\nnext\n");
+        assert_eq!(debugger.vm.ip, 3);
+        assert!(debugger.vm.frames.is_empty());
+        assert!(out.contains("-> 3: pushi 1"));
+        assert_eq!(io.stdout, b"2");
+    }
+
+    #[test]
+    fn delete_and_finish() {
+        let (debugger, out, io) =
+            debug_session(COUNTDOWN, "break loop\ndelete 1\ncontinue\nstep\nquit\nstep\n");
+        assert_eq!(debugger.status(), Some(&ExitStatus::Ok));
+        assert!(out.contains("Program finished: ok"));
+        assert_eq!(out.matches("Program finished").count(), 2);
+        assert_eq!(io.stdout, b"21");
+    }
+
+    #[test]
+    fn disasm_and_set_ip() {
+        let (debugger, out, _) = debug_session(COUNTDOWN, "break 2\nset ip show\ndisasm 1\n");
+        assert_eq!(debugger.vm.ip, 8);
+        assert!(out.contains("      7: exit\n   show:\n->    8: print\n      9: ret"));
+    }
+
+    #[test]
+    fn reverse_step() {
+        let (debugger, out, _) =
+            debug_session(COUNTDOWN, "step 4
+reverse-step
+reverse-step 2
+stack
+");
+        assert_eq!(debugger.vm.ip, 1);
+        assert!(out.contains("-> 9: ret"));
+        assert!(out.contains("-> 8: print"));
+        assert!(out.ends_with("-> 1: dup  (test.aa:2)\n   2 | loop:   dup\n(agar) [0] 2 (Int)\n(agar) \n"));
+    }
+
+    #[test]
+    fn reverse_continue() {
+        let (debugger, out, _) = debug_session(
+            COUNTDOWN,
+            "continue
+break show
+reverse-continue
+reverse-continue
+reverse-continue
+",
+        );
+        assert!(out.contains("Program finished: ok"));
+        assert!(out.contains("Breakpoint 1\n-> 8: print"));
+        assert!(out.contains("At the start of the recorded history\n-> 0: pushi 2"));
+        assert_eq!(debugger.vm.ip, 0);
+        assert_eq!(debugger.status(), None);
+    }
+
+    #[test]
+    fn origin() {
+        let (debugger, out, _) = debug_session(COUNTDOWN, "break 6
+continue
+continue
+origin 0
+");
+        assert!(out.contains("[0] 1 was pushed by:\n-> 4: sub"));
+        assert_eq!(debugger.vm.ip, 4);
+    }
+}
+
+mod opt {
+    use agar_asm::Assembler;
+    use agar_core::Program;
+
+    use crate::*;
+
+    /// Runs `source` as written and after `agar_opt::optimize`, checking that
+    /// both runs behave the same. Returns the optimized program's length.
+    fn same_output(source: &str, input: &str) -> (usize, usize) {
+        let program = Assembler::new(source.to_string()).parse_source().unwrap();
+        let mut optimized = program.clone();
+        agar_opt::optimize(&mut optimized);
+
+        let run = |program: Program| {
+            let mut vm = Interpreter::new();
+            vm.load_program(program).unwrap();
+            let mut io = MemoryIo::with_input(input);
+            let status = vm.run(&mut io);
+            (status.error_kind(), io.stdout, io.stderr, vm.stack)
+        };
+        let len = (program.ops.len(), optimized.ops.len());
+        assert_eq!(run(program), run(optimized));
+        len
+    }
+
+    #[test]
+    fn countdown() {
+        let source = "        pushi 3
+        nop
+loop:   dup
+        call show
+        jump next
+next:   pushi 1
+        sub
+        dup
+        pushi 7
+        pop
+        cjump loop
+        jump end
+show:   print
+        pushi 10
+        printchar
+        ret
+end:    jump done
+done:   exit
+";
+        assert_eq!(same_output(source, ""), (18, 12));
+    }
+
+    #[test]
+    fn threaded_loop() {
+        let source = "        readint
+top:    dup
+        not
+        not
+        pop
+        pop
+        cjump dec
+        jump out
+dec:    jump sub1
+sub1:   pushi 1
+        sub
+        dup
+        print
+        dup
+        cjump top
+out:    jump fin
+fin:    pushi 42
+        print
+";
+        let (before, after) = same_output(source, "4\n");
+        assert!(after < before);
+        same_output(source, "0\n");
+    }
+
+    #[test]
+    fn folded_and_unreachable() {
+        let source = "        pushi 2
+        pushi 3
+        mul
+        pushi 4
+        add
+        call show
+        pushi 1
+        not
+        cjump skip
+        pushi 99
+        print
+skip:   pushf 0.5
+        pushf 0.25
+        sub
+        call show
+        exit
+        pushi 13
+        print
+show:   print
+        pushi 10
+        printchar
+        ret
+";
+        let (before, after) = same_output(source, "");
+        assert_eq!((before, after), (22, 13));
+    }
+
+    #[test]
+    fn errors_stay_the_same() {
+        let source = "        nop
+        pushf 1.5
+        jump next
+next:   pushi 1
+        add
+";
+        same_output(source, "");
     }
 }
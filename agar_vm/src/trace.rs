@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::io::Write;
+use std::rc::Rc;
+
+use agar_core::{Data, Operands};
+
+use crate::{Interpreter, StepResult};
+
+/// Hooks called around every executed instruction.
+pub trait Tracer {
+    fn before_step(&mut self, _vm: &Interpreter) {}
+
+    fn after_step(&mut self, _vm: &Interpreter, _result: &StepResult) {}
+}
+
+/// Lets the caller keep a handle to a tracer and read its results after the run.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn before_step(&mut self, vm: &Interpreter) {
+        self.borrow_mut().before_step(vm);
+    }
+
+    fn after_step(&mut self, vm: &Interpreter, result: &StepResult) {
+        self.borrow_mut().after_step(vm, result);
+    }
+}
+
+/// Tracers attached to an interpreter. Cloning an interpreter doesn't clone them.
+#[derive(Default)]
+pub(crate) struct Tracers(pub(crate) Vec<Box<dyn Tracer>>);
+
+impl Clone for Tracers {
+    fn clone(&self) -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl Debug for Tracers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Tracers({})", self.0.len())
+    }
+}
+
+/// Human-readable trace: `ip`, mnemonic, operand and the top of the stack
+/// before each instruction.
+pub struct TextTracer {
+    out: Box<dyn Write>,
+    depth: usize,
+}
+
+impl TextTracer {
+    /// Shows up to `depth` of the topmost stack slots.
+    pub fn new(out: Box<dyn Write>, depth: usize) -> Self {
+        Self { out, depth }
+    }
+}
+
+impl Tracer for TextTracer {
+    fn before_step(&mut self, vm: &Interpreter) {
+        let instr = match vm.program.get(vm.ip) {
+            Some(instr) => instr.to_string(),
+            None => return,
+        };
+        let stack = vm.stack();
+        let top = &stack[stack.len().saturating_sub(self.depth)..];
+        let slots: Vec<String> = top.iter().map(|data| data.to_string()).collect();
+        let more = if top.len() < stack.len() { ".. " } else { "" };
+        let _ = writeln!(self.out, "{:>5}  {:<20} [{}{}]", vm.ip, instr, more, slots.join(" "));
+    }
+
+    fn after_step(&mut self, _vm: &Interpreter, result: &StepResult) {
+        if !matches!(result, StepResult::Ok) {
+            let _ = writeln!(self.out, "       -> {result:?}");
+        }
+        let _ = self.out.flush();
+    }
+}
+
+/// Machine-readable trace with one JSON object per executed instruction:
+/// `{"step":0,"ip":0,"op":"pushi","arg":5,"depth":0,"stack":[]}`.
+pub struct JsonTracer {
+    out: Box<dyn Write>,
+    depth: usize,
+    step: u64,
+}
+
+impl JsonTracer {
+    /// Records up to `depth` of the topmost stack slots.
+    pub fn new(out: Box<dyn Write>, depth: usize) -> Self {
+        Self { out, depth, step: 0 }
+    }
+}
+
+fn json_value(data: &Data) -> String {
+    match data {
+        Data::Int(a) => a.to_string(),
+        Data::Float(a) => format!("\"{a}\""),
+    }
+}
+
+impl Tracer for JsonTracer {
+    fn before_step(&mut self, vm: &Interpreter) {
+        let instr = match vm.program.get(vm.ip) {
+            Some(instr) => instr,
+            None => return,
+        };
+        let arg = match instr.operands {
+            Operands::Zero => "null".to_string(),
+            Operands::One(data) => json_value(&data),
+        };
+        let stack = vm.stack();
+        let top = &stack[stack.len().saturating_sub(self.depth)..];
+        let slots: Vec<String> = top.iter().map(json_value).collect();
+        let _ = writeln!(
+            self.out,
+            "{{\"step\":{},\"ip\":{},\"op\":\"{}\",\"arg\":{},\"depth\":{},\"stack\":[{}]}}",
+            self.step,
+            vm.ip,
+            instr.op_code.mnemonic(),
+            arg,
+            stack.len(),
+            slots.join(",")
+        );
+        self.step += 1;
+    }
+
+    fn after_step(&mut self, _vm: &Interpreter, result: &StepResult) {
+        if let StepResult::Error(e) = result {
+            let message = e.to_string().replace('\\', "\\\\").replace('"', "\\\"");
+            let message = message.replace('\n', "\\n");
+            let _ = writeln!(self.out, "{{\"error\":\"{message}\"}}");
+        }
+        let _ = self.out.flush();
+    }
+}